@@ -0,0 +1,198 @@
+use std::fmt;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// What a process runs under, as told by its control group.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Owner {
+    /// A system service, e.g. `nginx.service`.
+    Service { unit: String },
+    /// A scope or service of some user's systemd instance.
+    UserUnit { uid: u32, unit: String },
+    /// A login session, `session-<id>.scope`.
+    Session { uid: Option<u32>, id: String },
+    /// Any other systemd scope.
+    Scope { unit: String },
+    /// A Slurm job and, unless the process runs outside any step, its step:
+    /// a number, `batch`, `extern` or `interactive`.
+    Slurm { job: u64, step: Option<String> },
+    /// A Kubernetes pod and the ID of its container, if the process is in
+    /// one.
+    Pod {
+        uid: String,
+        container: Option<String>,
+    },
+    /// A container outside Kubernetes.
+    Container { runtime: String, id: String },
+}
+
+impl fmt::Display for Owner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Owner::Service { unit } | Owner::Scope { unit } => write!(f, "{}", unit),
+            Owner::UserUnit { uid, unit } => write!(f, "user@{}/{}", uid, unit),
+            Owner::Session { id, .. } => write!(f, "session {}", id),
+            Owner::Slurm { job, step: None } => write!(f, "slurm job {}", job),
+            Owner::Slurm {
+                job,
+                step: Some(step),
+            } => write!(f, "slurm job {}.{}", job, step),
+            Owner::Pod { uid, container } => {
+                write!(f, "pod {}", uid)?;
+                match container {
+                    Some(id) => write!(f, "/{}", short_id(id)),
+                    None => Ok(()),
+                }
+            }
+            Owner::Container { runtime, id } => write!(f, "{} {}", runtime, short_id(id)),
+        }
+    }
+}
+
+/// The first 12 characters of a container ID, as `docker ps` shows them.
+fn short_id(id: &str) -> &str {
+    &id[..id.len().min(12)]
+}
+
+fn is_container_id(id: &str) -> bool {
+    id.len() == 64 && id.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Systemd scopes of container runtimes, e.g. `docker-<id>.scope`.
+const SCOPE_PREFIXES: &[(&str, &str)] = &[
+    ("docker-", "docker"),
+    ("libpod-", "podman"),
+    ("cri-containerd-", "containerd"),
+    ("crio-", "cri-o"),
+];
+
+/// The runtime and ID of the container `part` stands for, if any; `parent`
+/// names the runtime of bare IDs.
+fn container(parent: Option<&str>, part: &str) -> Option<(String, String)> {
+    if is_container_id(part) {
+        let runtime = match parent {
+            Some("docker") => "docker",
+            _ => "containerd",
+        };
+        return Some((runtime.to_string(), part.to_string()));
+    }
+    let unit = part.strip_suffix(".scope")?;
+    SCOPE_PREFIXES.iter().find_map(|(prefix, runtime)| {
+        let id = unit.strip_prefix(prefix)?;
+        is_container_id(id).then(|| (runtime.to_string(), id.to_string()))
+    })
+}
+
+/// The pod UID in `pod<uid>` or `kubepods-<qos>-pod<uid_with_underscores>.slice`.
+fn pod_uid(part: &str) -> Option<String> {
+    let name = part.strip_suffix(".slice").unwrap_or(part);
+    let uid = &name[name.rfind("pod")? + 3..];
+    let valid = uid.len() == 36
+        && uid
+            .bytes()
+            .all(|b| b.is_ascii_hexdigit() || b == b'-' || b == b'_');
+    valid.then(|| uid.replace('_', "-"))
+}
+
+/// Classifies a control group path.
+///
+/// ```
+/// use gmon::cgroup::{classify, Owner};
+///
+/// assert_eq!(
+///     classify("/system.slice/nvidia-persistenced.service"),
+///     Some(Owner::Service { unit: String::from("nvidia-persistenced.service") }),
+/// );
+/// assert_eq!(
+///     classify("/user.slice/user-1000.slice/session-4.scope"),
+///     Some(Owner::Session { uid: Some(1000), id: String::from("4") }),
+/// );
+/// assert_eq!(
+///     classify("/kubepods/burstable/pod0f2c6a1e-1b7f-4d8e-9c3a-2a9d6b3c4e5f"),
+///     Some(Owner::Pod {
+///         uid: String::from("0f2c6a1e-1b7f-4d8e-9c3a-2a9d6b3c4e5f"),
+///         container: None,
+///     }),
+/// );
+/// assert_eq!(classify("/"), None);
+/// ```
+pub fn classify(path: &str) -> Option<Owner> {
+    let parts = path
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>();
+    let parent = |i: usize| i.checked_sub(1).map(|i| parts[i]);
+
+    if parts.iter().any(|part| part.starts_with("kubepods")) {
+        if let Some(i) = parts.iter().position(|part| pod_uid(part).is_some()) {
+            return Some(Owner::Pod {
+                uid: pod_uid(parts[i]).expect("position found a pod"),
+                container: parts
+                    .get(i + 1)
+                    .and_then(|part| container(Some(parts[i]), part))
+                    .map(|(_, id)| id),
+            });
+        }
+    }
+    if let Some(i) = parts.iter().position(|part| {
+        part.strip_prefix("job_")
+            .is_some_and(|id| id.parse::<u64>().is_ok())
+    }) {
+        return Some(Owner::Slurm {
+            job: parts[i]["job_".len()..]
+                .parse()
+                .expect("position parsed the ID"),
+            step: parts
+                .get(i + 1)
+                .and_then(|part| part.strip_prefix("step_"))
+                .map(str::to_string),
+        });
+    }
+    if let Some((runtime, id)) = (0..parts.len())
+        .rev()
+        .find_map(|i| container(parent(i), parts[i]))
+    {
+        return Some(Owner::Container { runtime, id });
+    }
+
+    let uid_of = |part: &str| {
+        part.strip_prefix("user-")?
+            .strip_suffix(".slice")?
+            .parse::<u32>()
+            .ok()
+    };
+    let unit = parts
+        .iter()
+        .rev()
+        .find(|part| part.ends_with(".service") || part.ends_with(".scope"))?;
+    if let Some(i) = parts.iter().position(|part| part.starts_with("user@")) {
+        let uid = parts[i]["user@".len()..]
+            .strip_suffix(".service")?
+            .parse()
+            .ok()?;
+        return Some(Owner::UserUnit {
+            uid,
+            unit: unit.to_string(),
+        });
+    }
+    if let Some(id) = unit
+        .strip_prefix("session-")
+        .and_then(|unit| unit.strip_suffix(".scope"))
+    {
+        return Some(Owner::Session {
+            uid: parts.iter().find_map(|part| uid_of(part)),
+            id: id.to_string(),
+        });
+    }
+    if unit.ends_with(".service") {
+        Some(Owner::Service {
+            unit: unit.to_string(),
+        })
+    } else {
+        Some(Owner::Scope {
+            unit: unit.to_string(),
+        })
+    }
+}
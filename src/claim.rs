@@ -0,0 +1,260 @@
+use std::fs;
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use sysinfo::{RefreshKind, System, SystemExt};
+use thiserror::Error;
+
+use crate::process::pid_alive;
+use crate::users::user_name;
+
+/// Where claims are kept unless configured otherwise.
+pub const DEFAULT_DIR: &str = "/run/gmon/locks";
+
+#[derive(Error, Debug)]
+pub enum ClaimError {
+    #[error("{uuid} is already claimed by {user}")]
+    Held { uuid: String, user: String },
+    #[error("{uuid} is claimed by {user}, not by you")]
+    NotOwner { uuid: String, user: String },
+    #[error("{0} is not claimed")]
+    NotClaimed(String),
+    #[error("{0}: {1}")]
+    Io(PathBuf, io::Error),
+}
+
+/// An advisory reservation of one GPU.
+///
+/// A claim lasts until it is released, until `expires`, or until the process
+/// `pid` exits, whichever comes first. Liveness of `pid` can only be checked
+/// on `host`; elsewhere only the expiry applies.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct Claim {
+    pub uuid: String,
+    pub user: String,
+    pub uid: u32,
+    pub host: String,
+    pub pid: u32,
+    pub note: Option<String>,
+    pub created: DateTime<Local>,
+    pub expires: Option<DateTime<Local>>,
+}
+
+fn hostname() -> String {
+    System::new_with_specifics(RefreshKind::new())
+        .host_name()
+        .unwrap_or_default()
+}
+
+impl Claim {
+    /// A claim on `uuid` by the current user, lasting as long as `pid`.
+    pub fn new(uuid: &str, pid: u32) -> Self {
+        let uid = unsafe { libc::getuid() };
+        Claim {
+            uuid: uuid.to_string(),
+            user: user_name(uid).unwrap_or_else(|| uid.to_string()),
+            uid,
+            host: hostname(),
+            pid,
+            note: None,
+            created: Local::now(),
+            expires: None,
+        }
+    }
+}
+
+/// A directory of claims, one JSON file per GPU UUID.
+///
+/// Files are created atomically, so of two users claiming the same GPU at
+/// once exactly one succeeds. Stale claims are removed as they are found;
+/// in a sticky directory (mode 1777) only their owner or root can remove
+/// them, and to everyone else they stay in effect.
+///
+/// ```
+/// use gmon::claim::{Claim, ClaimStore};
+///
+/// let dir = std::env::temp_dir().join(format!("gmon-doc-{}", std::process::id()));
+/// let store = ClaimStore::new(&dir);
+/// store.acquire(&[Claim::new("GPU-1234", std::process::id())])?;
+/// assert_eq!(store.load().len(), 1);
+/// assert!(store.acquire(&[Claim::new("GPU-1234", std::process::id())]).is_err());
+/// store.release("GPU-1234", unsafe { libc::getuid() })?;
+/// assert!(store.load().is_empty());
+/// # std::fs::remove_dir_all(&dir).ok();
+/// # Ok::<(), gmon::claim::ClaimError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ClaimStore {
+    dir: PathBuf,
+    host: String,
+}
+
+impl ClaimStore {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        ClaimStore {
+            dir: dir.as_ref().to_path_buf(),
+            host: hostname(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn path(&self, uuid: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", uuid))
+    }
+
+    /// A file only this process writes to, next to the claims.
+    fn scratch(&self, uuid: &str, purpose: &str) -> PathBuf {
+        self.dir
+            .join(format!(".{}.{}.{}", uuid, std::process::id(), purpose))
+    }
+
+    fn read(path: &Path) -> Option<Claim> {
+        let text = fs::read_to_string(path).ok()?;
+        serde_json::from_str(&text).ok()
+    }
+
+    pub fn is_stale(&self, claim: &Claim) -> bool {
+        claim.expires.is_some_and(|expires| expires <= Local::now())
+            || (claim.host == self.host && !pid_alive(claim.pid))
+    }
+
+    /// Removes `claim` unless it was replaced since it was read, and tells
+    /// whether it is gone. Only its owner or root can remove it from a
+    /// sticky directory.
+    fn reap(&self, claim: &Claim) -> bool {
+        let path = self.path(&claim.uuid);
+        let moved = self.scratch(&claim.uuid, "reap");
+        match fs::rename(&path, &moved) {
+            Ok(()) => {}
+            Err(e) => return e.kind() == ErrorKind::NotFound,
+        }
+        if Self::read(&moved).as_ref() != Some(claim) {
+            // Claimed again in between; put the new claim back.
+            let _ = fs::hard_link(&moved, &path);
+        }
+        let _ = fs::remove_file(&moved);
+        true
+    }
+
+    /// The claims in effect, sorted by UUID. Stale claims are reaped and
+    /// unreadable files skipped; a missing directory holds no claims.
+    ///
+    /// Stale claims that cannot be reaped, such as another user's in a
+    /// sticky directory, still hold their GPU until their owner or root
+    /// removes them; [`ClaimStore::is_stale`] tells them apart.
+    pub fn load(&self) -> Vec<Claim> {
+        let entries = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries,
+            Err(_) => return vec![],
+        };
+        let mut claims = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| {
+                let name = entry.file_name();
+                let name = name.to_string_lossy();
+                !name.starts_with('.') && name.ends_with(".json")
+            })
+            .filter_map(|entry| Self::read(&entry.path()))
+            .filter(|claim| !self.is_stale(claim) || !self.reap(claim))
+            .collect::<Vec<_>>();
+        claims.sort_by(|a, b| a.uuid.cmp(&b.uuid));
+        claims
+    }
+
+    fn create_dir(&self) -> Result<(), ClaimError> {
+        if self.dir.is_dir() {
+            return Ok(());
+        }
+        let io_err = |e| ClaimError::Io(self.dir.clone(), e);
+        fs::create_dir_all(&self.dir).map_err(io_err)?;
+        // Shared by every user, but only owners may remove their files.
+        fs::set_permissions(&self.dir, fs::Permissions::from_mode(0o1777)).map_err(io_err)
+    }
+
+    fn acquire_one(&self, claim: &Claim) -> Result<(), ClaimError> {
+        let path = self.path(&claim.uuid);
+        let tmp = self.scratch(&claim.uuid, "new");
+        let text = serde_json::to_string_pretty(claim).expect("claim is always serializable");
+        let write = fs::File::create(&tmp).and_then(|mut file| file.write_all(text.as_bytes()));
+        if let Err(e) = write {
+            let _ = fs::remove_file(&tmp);
+            return Err(ClaimError::Io(tmp, e));
+        }
+        // Linking fails if the claim exists, unlike renaming over it.
+        let mut res = fs::hard_link(&tmp, &path);
+        if matches!(&res, Err(e) if e.kind() == ErrorKind::AlreadyExists) {
+            if let Some(held) = Self::read(&path).filter(|held| self.is_stale(held)) {
+                if self.reap(&held) {
+                    res = fs::hard_link(&tmp, &path);
+                }
+            }
+        }
+        let _ = fs::remove_file(&tmp);
+        match res {
+            Ok(()) => Ok(()),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => Err(ClaimError::Held {
+                uuid: claim.uuid.clone(),
+                user: Self::read(&path).map(|held| held.user).unwrap_or_default(),
+            }),
+            Err(e) => Err(ClaimError::Io(path, e)),
+        }
+    }
+
+    /// Takes all of `claims` or, if any is held already, none of them.
+    pub fn acquire(&self, claims: &[Claim]) -> Result<(), ClaimError> {
+        self.create_dir()?;
+        for (i, claim) in claims.iter().enumerate() {
+            if let Err(e) = self.acquire_one(claim) {
+                for taken in &claims[..i] {
+                    let _ = fs::remove_file(self.path(&taken.uuid));
+                }
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes the claim on `uuid`, which the current user owns, last as long
+    /// as `pid` instead; used to take a claim before starting the process
+    /// that will hold it.
+    pub fn hand_over(&self, uuid: &str, pid: u32) -> Result<(), ClaimError> {
+        let path = self.path(uuid);
+        let claim = Self::read(&path).ok_or_else(|| ClaimError::NotClaimed(uuid.to_string()))?;
+        if claim.uid != unsafe { libc::getuid() } {
+            return Err(ClaimError::NotOwner {
+                uuid: uuid.to_string(),
+                user: claim.user,
+            });
+        }
+        let tmp = self.scratch(uuid, "new");
+        let text = serde_json::to_string_pretty(&Claim { pid, ..claim })
+            .expect("claim is always serializable");
+        let res = fs::write(&tmp, text).and_then(|_| fs::rename(&tmp, &path));
+        if let Err(e) = res {
+            let _ = fs::remove_file(&tmp);
+            return Err(ClaimError::Io(path, e));
+        }
+        Ok(())
+    }
+
+    /// Releases the claim on `uuid` if `uid` owns it or is root.
+    pub fn release(&self, uuid: &str, uid: u32) -> Result<Claim, ClaimError> {
+        let path = self.path(uuid);
+        let claim = Self::read(&path).ok_or_else(|| ClaimError::NotClaimed(uuid.to_string()))?;
+        if claim.uid != uid && uid != 0 {
+            return Err(ClaimError::NotOwner {
+                uuid: uuid.to_string(),
+                user: claim.user,
+            });
+        }
+        fs::remove_file(&path).map_err(|e| ClaimError::Io(path, e))?;
+        Ok(claim)
+    }
+}
@@ -0,0 +1,159 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+use chrono::Local;
+use gmon::claim::{Claim, ClaimError, ClaimStore};
+use gmon::collector::Collector;
+use gmon::select::{free_gpus, sample_window, Criteria, FreeGpu};
+use gmon::vendors::error::BackendError;
+use gmon::vendors::traits::{GpuInfo, GpuProviderInfo};
+use thiserror::Error;
+
+use crate::cli::{ClaimArgs, ReleaseArgs};
+use crate::free::visible_devices;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Backend(#[from] BackendError),
+    #[error(transparent)]
+    Claim(#[from] ClaimError),
+    #[error("only {found} of {wanted} GPUs are free")]
+    NotEnough { found: usize, wanted: usize },
+    #[error("no GPU {0}")]
+    NoGpu(String),
+}
+
+/// Claims the first `count` of `gpus` that nobody claimed meanwhile, with
+/// claims made by `make`.
+pub fn claim_gpus(
+    store: &ClaimStore,
+    gpus: &[FreeGpu],
+    count: usize,
+    make: impl Fn(&str) -> Claim,
+) -> Result<Vec<FreeGpu>, Error> {
+    // GPUs found held when claiming them; each attempt rules out at least
+    // one, so this ends.
+    let mut lost = HashSet::new();
+    loop {
+        // Another claim may have appeared since the sample was taken.
+        let claimed = store.load();
+        let picked = gpus
+            .iter()
+            .filter(|gpu| match &gpu.uuid {
+                Some(uuid) => {
+                    !lost.contains(uuid) && !claimed.iter().any(|claim| &claim.uuid == uuid)
+                }
+                None => false,
+            })
+            .take(count)
+            .cloned()
+            .collect::<Vec<_>>();
+        if picked.len() < count {
+            return Err(Error::NotEnough {
+                found: picked.len(),
+                wanted: count,
+            });
+        }
+        let claims = picked
+            .iter()
+            .filter_map(|gpu| gpu.uuid.as_deref())
+            .map(&make)
+            .collect::<Vec<_>>();
+        match store.acquire(&claims) {
+            Ok(()) => return Ok(picked),
+            // Lost a race for one of them; try the rest.
+            Err(ClaimError::Held { uuid, .. }) => {
+                lost.insert(uuid);
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Claims free GPUs and prints `CUDA_VISIBLE_DEVICES` for them.
+pub fn claim<'a, P, G>(
+    collector: &mut Collector<'a, P>,
+    store: &ClaimStore,
+    args: &ClaimArgs,
+    interval: Duration,
+) -> Result<(), Error>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    let window = Duration::from_secs_f64(args.criteria.window);
+    let samples = sample_window(collector, window, interval)?;
+    let criteria = Criteria {
+        own_claims: false,
+        ..args.criteria.criteria()
+    };
+    let gpus = free_gpus(&samples, &criteria);
+    let pid = args
+        .pid
+        .unwrap_or_else(|| unsafe { libc::getppid() } as u32);
+    // Durations too long to represent never expire in practice.
+    let expires = args.duration.and_then(|duration| {
+        let duration = chrono::Duration::from_std(duration).ok()?;
+        Local::now().checked_add_signed(duration)
+    });
+    let picked = claim_gpus(store, &gpus, args.count, |uuid| Claim {
+        note: args.note.clone(),
+        expires,
+        ..Claim::new(uuid, pid)
+    })?;
+    println!(
+        "CUDA_VISIBLE_DEVICES={}",
+        visible_devices(&picked, args.uuids)
+    );
+    Ok(())
+}
+
+/// Releases the claims named by index or UUID, or all of the caller's.
+pub fn release<'a, P, G>(
+    collector: &mut Collector<'a, P>,
+    store: &ClaimStore,
+    args: &ReleaseArgs,
+) -> Result<(), Error>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    let uid = unsafe { libc::getuid() };
+    let claims = store.load();
+    let uuids = if args.all {
+        claims
+            .iter()
+            .filter(|claim| claim.uid == uid)
+            .map(|claim| claim.uuid.clone())
+            .collect()
+    } else {
+        let snapshot = collector.sample()?;
+        args.gpus
+            .iter()
+            .map(|gpu| {
+                let by_index = gpu.parse::<u32>().ok().and_then(|index| {
+                    snapshot
+                        .devices
+                        .iter()
+                        .find(|device| device.index == index)
+                        .and_then(|device| device.uuid.as_ref())
+                        .map(|uuid| uuid.val.clone())
+                });
+                by_index
+                    .or_else(|| {
+                        claims
+                            .iter()
+                            .find(|claim| claim.uuid.starts_with(gpu.as_str()))
+                            .map(|claim| claim.uuid.clone())
+                    })
+                    .ok_or_else(|| Error::NoGpu(gpu.clone()))
+            })
+            .collect::<Result<Vec<_>, _>>()?
+    };
+    for uuid in uuids {
+        let claim = store.release(&uuid, uid)?;
+        eprintln!("gmon: released {} (claimed by {})", claim.uuid, claim.user);
+    }
+    Ok(())
+}
@@ -1,8 +1,433 @@
-use clap::Parser;
+use std::str::FromStr;
+use std::time::Duration;
+
+use clap::{ArgEnum, Parser, Subcommand};
+use gmon::export::csv::CsvOptions;
+use gmon::vendors::filter::{Activity, DeviceFilter, IndexSet};
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+
+use crate::template::{GpuTemplate, ProcessTemplate};
 
 #[derive(Debug, Parser)]
 #[clap(version, about)]
 pub struct Args {
-    #[clap(long, short = 'c')]
+    #[clap(long, short = 'c', global = true)]
     pub container_support: bool,
+    /// Skip container lookups even if the config file enables them
+    #[clap(long, conflicts_with = "container-support", global = true)]
+    pub no_container_support: bool,
+    /// Attribute GPU processes to Slurm jobs
+    #[clap(long, global = true)]
+    pub slurm: bool,
+    /// Only the processes of Slurm job ID and the GPUs they use
+    #[clap(long, value_name = "ID", global = true)]
+    pub slurm_job: Option<u64>,
+    /// Attribute GPUs and processes to Kubernetes pods via the kubelet
+    #[clap(long, global = true)]
+    pub kubernetes: bool,
+    /// Where readings come from; `fake` serves fixed idle GPUs for testing
+    #[clap(long, arg_enum, global = true)]
+    pub backend: Option<Backend>,
+    /// Refresh every SECS seconds (the configured interval if omitted),
+    /// redrawing in place
+    #[clap(long, short = 'i', value_name = "SECS", min_values = 0)]
+    pub interval: Option<Option<f64>>,
+    /// Only GPUs with these indices, e.g. 0,2-3
+    #[clap(long, value_name = "LIST", global = true)]
+    pub gpus: Option<IndexSet>,
+    /// Only GPUs whose UUID starts with UUID (repeatable)
+    #[clap(long, value_name = "UUID", multiple_occurrences = true, global = true)]
+    pub uuid: Vec<String>,
+    /// Only GPUs whose name matches REGEX
+    #[clap(long, value_name = "REGEX", global = true)]
+    pub name_regex: Option<Regex>,
+    /// Only GPUs running processes or reporting utilization
+    #[clap(long, conflicts_with = "idle", global = true)]
+    pub busy: bool,
+    /// Only GPUs without processes or utilization
+    #[clap(long, global = true)]
+    pub idle: bool,
+    /// Print the snapshot as JSON
+    #[clap(long, conflicts_with_all = &["interval", "json-lines"])]
+    pub json: bool,
+    /// Print one JSON document per line, once per interval with -i
+    #[clap(long)]
+    pub json_lines: bool,
+    /// Print GPU fields as CSV, e.g. index,name,memory.used
+    #[clap(
+        long,
+        value_name = "FIELDS",
+        conflicts_with_all = &["json", "json-lines", "query-compute-apps"]
+    )]
+    pub query_gpu: Option<String>,
+    /// Print GPU process fields as CSV, e.g. pid,process_name,used_memory
+    #[clap(
+        long,
+        value_name = "FIELDS",
+        conflicts_with_all = &["json", "json-lines"]
+    )]
+    pub query_compute_apps: Option<String>,
+    /// Output format: gpustat-json, a line template such as
+    /// '{index} {name:20} {temp:>3:auto}°C', or csv/tsv (optionally with
+    /// noheader,nounits) for queries
+    #[clap(long, value_name = "FORMAT")]
+    pub format: Option<Format>,
+    /// Template for lines listing each GPU's processes, e.g. '  {pid:>7} {command}'
+    #[clap(long, value_name = "TEMPLATE")]
+    pub process_format: Option<ProcessTemplate>,
+    /// NVML library to try before the default locations (repeatable)
+    #[clap(
+        long = "nvml-lib",
+        env = "GMON_NVML_LIB",
+        value_delimiter = ':',
+        multiple_occurrences = true,
+        global = true
+    )]
+    pub nvml_lib: Vec<String>,
+    #[clap(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Diagnose why GPUs are not visible
+    Doctor,
+    /// Full-screen interface with history and a process table
+    Tui,
+    /// Print the JSON Schema of the --json output
+    Schema,
+    /// Print CUDA_VISIBLE_DEVICES for GPUs nobody else is using
+    Free(FreeArgs),
+    /// Wait until N GPUs are free, then run COMMAND with CUDA_VISIBLE_DEVICES
+    /// set to them
+    Wait(WaitArgs),
+    /// Claim free GPUs so nobody else's selection picks them
+    Claim(ClaimArgs),
+    /// Give up claims
+    Release(ReleaseArgs),
+    /// Signal GPU processes selected by GPU, user, container or PID
+    Kill(KillArgs),
+    /// Run commands in turn as GPUs become free
+    Queue {
+        #[clap(subcommand)]
+        command: QueueCommand,
+    },
+    /// Inspect the configuration
+    Config {
+        #[clap(subcommand)]
+        command: ConfigCommand,
+    },
+    /// Serve metrics over HTTP
+    Serve {
+        /// Expose /metrics in the Prometheus text format
+        #[clap(long)]
+        prometheus: bool,
+        /// Address to listen on
+        #[clap(long, value_name = "ADDR", default_value = "127.0.0.1:9835")]
+        listen: String,
+    },
+}
+
+impl Args {
+    pub fn device_filter(&self) -> DeviceFilter {
+        DeviceFilter {
+            indices: self.gpus.clone(),
+            uuids: self.uuid.clone(),
+            name: self.name_regex.clone(),
+            activity: match (self.busy, self.idle) {
+                (true, _) => Some(Activity::Busy),
+                (_, true) => Some(Activity::Idle),
+                _ => None,
+            },
+        }
+    }
+}
+
+/// What makes a GPU free, shared by `free` and `wait`.
+#[derive(Debug, clap::Args)]
+pub struct CriteriaArgs {
+    /// Free memory needed, in GiB
+    #[clap(long, value_name = "GIB", default_value = "0")]
+    pub min_free: f64,
+    /// Highest utilization tolerated, in percent
+    #[clap(long, value_name = "PCT", default_value = "10")]
+    pub max_util: u32,
+    /// Sample for SECS seconds; a GPU must qualify in every sample
+    #[clap(long, value_name = "SECS", default_value = "0")]
+    pub window: f64,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct FreeArgs {
+    #[clap(flatten)]
+    pub criteria: CriteriaArgs,
+    /// Number of GPUs wanted; exit with status 1 if fewer qualify
+    #[clap(long, short = 'n', value_name = "N")]
+    pub count: Option<usize>,
+    /// List UUIDs instead of indices
+    #[clap(long)]
+    pub uuids: bool,
+    /// Print the selection as JSON
+    #[clap(long)]
+    pub json: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct WaitArgs {
+    /// Number of GPUs wanted
+    #[clap(value_name = "N")]
+    pub count: usize,
+    #[clap(flatten)]
+    pub criteria: CriteriaArgs,
+    /// Give up after SECS seconds, exiting with status 124
+    #[clap(long, value_name = "SECS")]
+    pub timeout: Option<f64>,
+    /// Check again every SECS seconds
+    #[clap(long, value_name = "SECS", default_value = "10")]
+    pub poll: f64,
+    /// Prefer GPUs sharing NVLink or a PCIe switch over the lowest indices
+    #[clap(long)]
+    pub prefer_local: bool,
+    /// Set UUIDs instead of indices in CUDA_VISIBLE_DEVICES
+    #[clap(long)]
+    pub uuids: bool,
+    /// Claim the GPUs for as long as COMMAND runs
+    #[clap(long)]
+    pub claim: bool,
+    /// Command to run once the GPUs are free
+    #[clap(last = true, required = true, value_name = "COMMAND")]
+    pub command: Vec<String>,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ClaimArgs {
+    #[clap(flatten)]
+    pub criteria: CriteriaArgs,
+    /// Number of GPUs to claim
+    #[clap(long, short = 'n', value_name = "N", default_value = "1")]
+    pub count: usize,
+    /// Why the GPUs are claimed, shown to everyone
+    #[clap(long, value_name = "TEXT")]
+    pub note: Option<String>,
+    /// Release the claim after DURATION, e.g. 90m or 8h
+    #[clap(long = "for", value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub duration: Option<Duration>,
+    /// Hold the claim while PID runs [default: the calling shell]
+    #[clap(long, value_name = "PID")]
+    pub pid: Option<u32>,
+    /// List UUIDs instead of indices
+    #[clap(long)]
+    pub uuids: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct ReleaseArgs {
+    /// GPU indices or UUIDs
+    #[clap(value_name = "GPU", required_unless_present = "all")]
+    pub gpus: Vec<String>,
+    /// Release all of your claims
+    #[clap(long, conflicts_with = "gpus")]
+    pub all: bool,
+}
+
+#[derive(Debug, clap::Args)]
+pub struct KillArgs {
+    /// Only processes on these GPUs, e.g. 3 or 0,2-3
+    #[clap(long, value_name = "LIST")]
+    pub gpu: Option<IndexSet>,
+    /// Only processes of USER, a name or uid
+    #[clap(long, value_name = "USER")]
+    pub user: Option<String>,
+    /// Only processes in the container with this name or ID prefix
+    #[clap(long, value_name = "NAME")]
+    pub container: Option<String>,
+    /// Only these PIDs (repeatable)
+    #[clap(long, value_name = "PID", multiple_occurrences = true)]
+    pub pid: Vec<u32>,
+    /// Signal to send, by name or number
+    #[clap(long, short = 's', value_name = "SIGNAL", default_value = "TERM")]
+    pub signal: Signal,
+    /// Send SIGKILL to processes still running SECS seconds after SIGNAL
+    #[clap(long, value_name = "SECS")]
+    pub grace: Option<f64>,
+    /// Print what would be done without signalling anything
+    #[clap(long)]
+    pub dry_run: bool,
+}
+
+/// A signal given by name, with or without `SIG`, or by number.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Signal(pub i32);
+
+const SIGNALS: &[(&str, i32)] = &[
+    ("HUP", libc::SIGHUP),
+    ("INT", libc::SIGINT),
+    ("QUIT", libc::SIGQUIT),
+    ("KILL", libc::SIGKILL),
+    ("USR1", libc::SIGUSR1),
+    ("USR2", libc::SIGUSR2),
+    ("TERM", libc::SIGTERM),
+    ("CONT", libc::SIGCONT),
+    ("STOP", libc::SIGSTOP),
+];
+
+impl Signal {
+    pub fn name(&self) -> String {
+        match SIGNALS.iter().find(|(_, num)| *num == self.0) {
+            Some((name, _)) => format!("SIG{}", name),
+            None => format!("signal {}", self.0),
+        }
+    }
+}
+
+impl FromStr for Signal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Ok(num) = s.parse::<i32>() {
+            return Ok(Signal(num));
+        }
+        let name = s.to_ascii_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+        SIGNALS
+            .iter()
+            .find(|(known, _)| *known == name)
+            .map(|(_, num)| Signal(*num))
+            .ok_or_else(|| format!("unknown signal \"{}\"", s))
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum QueueCommand {
+    /// Add a command to the queue
+    Submit(SubmitArgs),
+    /// Start queued jobs in order as GPUs become free
+    Run {
+        /// Check for free GPUs every SECS seconds
+        #[clap(long, value_name = "SECS", default_value = "10")]
+        poll: f64,
+    },
+    /// List jobs
+    Ls {
+        /// Print the jobs as JSON
+        #[clap(long)]
+        json: bool,
+    },
+    /// Remove queued jobs and stop running ones
+    Cancel {
+        #[clap(value_name = "ID", required = true)]
+        ids: Vec<u64>,
+    },
+}
+
+#[derive(Debug, clap::Args)]
+pub struct SubmitArgs {
+    /// Number of GPUs needed
+    #[clap(long, value_name = "N", default_value = "1")]
+    pub gpus: usize,
+    /// Free memory needed on each GPU, e.g. 20G or 512M; bare numbers are GiB
+    #[clap(long, value_name = "SIZE", default_value = "0", parse(try_from_str = parse_memory))]
+    pub mem: u64,
+    /// Highest utilization tolerated, in percent
+    #[clap(long, value_name = "PCT", default_value = "10")]
+    pub max_util: u32,
+    /// Command to run, in the current directory and environment
+    #[clap(last = true, required = true, value_name = "COMMAND")]
+    pub command: Vec<String>,
+}
+
+/// Parses memory sizes such as `512M`, `20G` or `1.5T` into MiB; bare numbers
+/// are GiB, like `--min-free`.
+pub fn parse_memory(s: &str) -> Result<u64, String> {
+    let invalid = || format!("invalid size \"{}\", expected e.g. 512M or 20G", s);
+    let (num, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(at) => s.split_at(at),
+        None => (s, "G"),
+    };
+    let scale = match unit.trim_end_matches(['i', 'B']) {
+        "M" => 1.0,
+        "G" => 1024.0,
+        "T" => 1024.0 * 1024.0,
+        _ => return Err(invalid()),
+    };
+    let mib = num.trim().parse::<f64>().map_err(|_| invalid())? * scale;
+    if !mib.is_finite() || mib < 0.0 || mib > u64::MAX as f64 {
+        return Err(invalid());
+    }
+    Ok(mib.ceil() as u64)
+}
+
+/// Parses durations such as `45s`, `90m`, `8h` or `2d`; bare numbers are
+/// seconds.
+pub fn parse_duration(s: &str) -> Result<Duration, String> {
+    let invalid = || format!("invalid duration \"{}\", expected e.g. 90m or 8h", s);
+    let (num, unit) = match s.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(at) => s.split_at(at),
+        None => (s, "s"),
+    };
+    let scale = match unit {
+        "s" => 1.0,
+        "m" => 60.0,
+        "h" => 3600.0,
+        "d" => 86400.0,
+        _ => return Err(invalid()),
+    };
+    let num = num.trim().parse::<f64>().map_err(|_| invalid())?;
+    Duration::try_from_secs_f64(num * scale).map_err(|_| invalid())
+}
+
+#[derive(Debug, Subcommand)]
+pub enum ConfigCommand {
+    /// Print the configuration in effect, after applying flags
+    Show,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ArgEnum, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Nvidia,
+    Fake,
+}
+
+#[derive(Debug, Clone)]
+pub enum Format {
+    Csv(CsvOptions),
+    GpustatJson,
+    Template(GpuTemplate),
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "gpustat-json" {
+            return Ok(Format::GpustatJson);
+        }
+        if s.contains('{') {
+            return s.parse().map(Format::Template).map_err(|e| e.to_string());
+        }
+        let mut parts = s.split(',').map(str::trim);
+        let mut opts = match parts.next() {
+            Some("csv") => CsvOptions::default(),
+            Some("tsv") => CsvOptions {
+                separator: "\t",
+                ..CsvOptions::default()
+            },
+            _ => {
+                return Err(format!(
+                    "unknown format \"{}\", expected csv, tsv, gpustat-json or a template",
+                    s
+                ))
+            }
+        };
+        for part in parts {
+            match part {
+                "noheader" => opts.header = false,
+                "nounits" => opts.units = false,
+                _ => return Err(format!("unknown format option \"{}\"", part)),
+            }
+        }
+        Ok(Format::Csv(opts))
+    }
 }
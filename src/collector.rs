@@ -0,0 +1,450 @@
+use std::collections::{BTreeSet, HashMap};
+use std::thread;
+use std::time::Instant;
+
+use chrono::Local;
+use sysinfo::{RefreshKind, System, SystemExt};
+
+use crate::cgroup::Owner;
+use crate::claim::ClaimStore;
+use crate::docker::container::Container;
+use crate::kubelet::{self, PodRef, PodResourcesClient};
+use crate::process::{clock_ticks_per_sec, load_containers, GProcess, ProcessData};
+use crate::procfs::ProcFs;
+use crate::slurm::{self, Slurm};
+use crate::snapshot::{Device, DeviceStatus, Snapshot};
+use crate::users::Names;
+use crate::vendors::error::{BackendError, DeviceError};
+use crate::vendors::filter::DeviceFilter;
+use crate::vendors::traits::{GpuInfo, GpuProviderInfo};
+use crate::vendors::types::{CudaVersion, Value};
+
+/// Fields that do not change while a device stays on the bus.
+#[derive(Debug, Clone)]
+struct StaticInfo {
+    uuid: Option<Value<String>>,
+    name: Option<Value<String>>,
+    pci_bus_id: Option<Value<String>>,
+    /// Read before the device was lost: still good for telling which device
+    /// is missing, but read again once it is back.
+    stale: bool,
+}
+
+impl StaticInfo {
+    fn into_stale(self) -> Self {
+        StaticInfo {
+            stale: true,
+            ..self
+        }
+    }
+}
+
+fn field<T>(res: Result<T, BackendError>, lost: &mut Option<String>) -> Option<T> {
+    match res {
+        Ok(val) => Some(val),
+        Err(e @ BackendError::GpuLost) => {
+            lost.get_or_insert(e.to_string());
+            None
+        }
+        Err(_) => None,
+    }
+}
+
+fn matches_identity(filter: &DeviceFilter, statics: Option<&StaticInfo>) -> bool {
+    filter.matches_identity(
+        statics
+            .and_then(|s| s.uuid.as_ref())
+            .map(|v| v.val.as_str()),
+        statics
+            .and_then(|s| s.name.as_ref())
+            .map(|v| v.val.as_str()),
+    )
+}
+
+/// Samples one device, or only its static fields when `filter` leaves it
+/// out.
+fn sample_device<'a, G: GpuInfo<'a>>(
+    index: u32,
+    gpu: &G,
+    cached: Option<StaticInfo>,
+    filter: &DeviceFilter,
+) -> (Option<Device>, StaticInfo) {
+    let mut lost = None;
+    let statics = match cached {
+        Some(statics) if !statics.stale => statics,
+        _ => StaticInfo {
+            uuid: field(gpu.uuid(), &mut lost),
+            name: field(gpu.name(), &mut lost),
+            pci_bus_id: field(gpu.pci_bus_id(), &mut lost),
+            stale: false,
+        },
+    };
+    if !matches_identity(filter, Some(&statics)) {
+        let statics = match lost {
+            Some(_) => statics.into_stale(),
+            None => statics,
+        };
+        return (None, statics);
+    }
+    let device = Device {
+        index,
+        status: DeviceStatus::Available,
+        uuid: statics.uuid.clone(),
+        name: statics.name.clone(),
+        pci_bus_id: statics.pci_bus_id.clone(),
+        utilization: field(gpu.utilization(), &mut lost),
+        temperature: field(gpu.temperature(), &mut lost),
+        power: field(gpu.power_info(), &mut lost),
+        memory: field(gpu.memory_info(), &mut lost),
+        clocks: field(gpu.clocks(), &mut lost),
+        counters: field(gpu.counters(), &mut lost),
+        processes: field(gpu.processes(), &mut lost).unwrap_or_default(),
+        mig_uuids: field(gpu.mig_uuids(), &mut lost).unwrap_or_default(),
+    };
+    let (device, statics) = match lost {
+        Some(reason) => (
+            Device {
+                status: DeviceStatus::Lost { reason },
+                ..device
+            },
+            statics.into_stale(),
+        ),
+        None => (device, statics),
+    };
+    (filter.matches_activity(&device).then_some(device), statics)
+}
+
+/// Percent of one core `proc` used since `last`, its CPU ticks at an earlier
+/// sample, or over its lifetime without one.
+fn cpu_percent(
+    proc: &GProcess,
+    ticks: u64,
+    last: Option<&(u64, Instant)>,
+    now: Instant,
+) -> Option<f32> {
+    let (used, secs) = match last {
+        Some(&(before, at)) => (ticks.saturating_sub(before), (now - at).as_secs_f64()),
+        None => (ticks, proc.elapsed? as f64),
+    };
+    (secs > 0.0).then(|| (used as f64 / clock_ticks_per_sec() as f64 / secs * 100.0) as f32)
+}
+
+/// A device that could not be opened, named after what was known of it.
+fn unreachable_device(e: DeviceError, statics: Option<&StaticInfo>) -> Device {
+    let reason = e.error.to_string();
+    Device {
+        index: e.index,
+        status: match e.error {
+            BackendError::GpuLost => DeviceStatus::Lost { reason },
+            _ => DeviceStatus::Unavailable { reason },
+        },
+        uuid: statics.and_then(|s| s.uuid.clone()),
+        name: statics.and_then(|s| s.name.clone()),
+        pci_bus_id: statics.and_then(|s| s.pci_bus_id.clone()),
+        utilization: None,
+        temperature: None,
+        power: None,
+        memory: None,
+        clocks: None,
+        counters: None,
+        processes: vec![],
+        mig_uuids: vec![],
+    }
+}
+
+/// Samples a provider repeatedly, caching everything that does not change
+/// between samples.
+///
+/// Devices are queried concurrently, one thread per device. Names, UUIDs and
+/// versions are read once; container lookups are only repeated when the set
+/// of GPU processes changes, and user and group names are looked up once.
+/// The CPU usage of processes is measured between consecutive samples.
+///
+/// ```
+/// use gmon::collector::Collector;
+/// use gmon::vendors::fake::FakeGpuProvider;
+///
+/// let provider = FakeGpuProvider::with_idle_devices(8);
+/// let mut collector = Collector::new(&provider, false);
+/// for _ in 0..3 {
+///     let snapshot = collector.sample()?;
+///     assert_eq!(snapshot.devices.len(), 8);
+/// }
+/// # Ok::<(), gmon::BackendError>(())
+/// ```
+pub struct Collector<'a, P> {
+    provider: &'a P,
+    container_support: bool,
+    sys: System,
+    host: String,
+    driver_version: Option<Value<String>>,
+    cuda_version: Option<CudaVersion>,
+    statics: HashMap<u32, StaticInfo>,
+    ctr_map: Option<HashMap<u32, Container>>,
+    gpu_pids: BTreeSet<u32>,
+    filter: DeviceFilter,
+    claims: Option<ClaimStore>,
+    names: Names,
+    procfs: ProcFs,
+    slurm: Option<Slurm>,
+    slurm_job: Option<u64>,
+    kubelet: Option<PodResourcesClient>,
+    /// Whether the last pod-resources call failed.
+    kubelet_failing: bool,
+    /// Pods of containers, by container ID, as their runtime recorded them.
+    container_pods: HashMap<String, Option<PodRef>>,
+    cpu_times: HashMap<u32, (u64, Instant)>,
+}
+
+impl<'a, P> Collector<'a, P> {
+    pub fn new<G>(provider: &'a P, container_support: bool) -> Self
+    where
+        P: GpuProviderInfo<'a, G>,
+        G: GpuInfo<'a>,
+    {
+        let sys = System::new_with_specifics(RefreshKind::new());
+        Collector {
+            provider,
+            container_support,
+            host: sys.host_name().unwrap_or_default(),
+            sys,
+            driver_version: provider.driver_version().ok(),
+            cuda_version: provider.cuda_version().ok(),
+            statics: HashMap::new(),
+            ctr_map: None,
+            gpu_pids: BTreeSet::new(),
+            filter: DeviceFilter::default(),
+            claims: None,
+            names: Names::default(),
+            procfs: ProcFs::default(),
+            slurm: None,
+            slurm_job: None,
+            kubelet: None,
+            kubelet_failing: false,
+            container_pods: HashMap::new(),
+            cpu_times: HashMap::new(),
+        }
+    }
+
+    /// The provider samples are taken from.
+    pub fn provider(&self) -> &'a P {
+        self.provider
+    }
+
+    /// Samples only the devices `filter` selects.
+    pub fn with_filter(self, filter: DeviceFilter) -> Self {
+        Collector { filter, ..self }
+    }
+
+    /// Reports the claims in `store` with each sample.
+    pub fn with_claims(self, store: ClaimStore) -> Self {
+        Collector {
+            claims: Some(store),
+            ..self
+        }
+    }
+
+    /// Reads CPU usage and control groups of processes from `procfs`.
+    pub fn with_procfs(self, procfs: ProcFs) -> Self {
+        Collector { procfs, ..self }
+    }
+
+    /// Attributes processes to Slurm jobs, described by `slurm`.
+    pub fn with_slurm(self, slurm: Slurm) -> Self {
+        Collector {
+            slurm: Some(slurm),
+            ..self
+        }
+    }
+
+    /// Keeps only the processes of Slurm job `job` and the devices they
+    /// run on; needs [`Collector::with_slurm`].
+    pub fn with_slurm_job(self, job: Option<u64>) -> Self {
+        Collector {
+            slurm_job: job,
+            ..self
+        }
+    }
+
+    /// Asks the kubelet behind `client` which containers were allocated
+    /// which GPUs, and attributes the processes of pods to their containers.
+    /// Failed calls are reported on stderr when they start failing.
+    pub fn with_kubelet(self, client: PodResourcesClient) -> Self {
+        Collector {
+            kubelet: Some(client),
+            ..self
+        }
+    }
+
+    pub fn sample<G>(&mut self) -> Result<Snapshot, BackendError>
+    where
+        P: GpuProviderInfo<'a, G>,
+        G: GpuInfo<'a> + Send,
+    {
+        let timestamp = Local::now();
+        let count = self.provider.device_count()?.val;
+        let statics = &self.statics;
+        let filter = &self.filter;
+        // Devices left out by index are not even opened.
+        let gpus = (0..count)
+            .filter(|index| filter.matches_index(*index))
+            .map(|index| (index, self.provider.gpu(index)))
+            .collect::<Vec<_>>();
+        let sampled = thread::scope(|s| {
+            let handles = gpus
+                .into_iter()
+                .map(|(index, gpu)| {
+                    let cached = statics.get(&index).cloned();
+                    let handle = s.spawn(move || match gpu {
+                        Ok(gpu) => {
+                            let (device, statics) = sample_device(index, &gpu, cached, filter);
+                            (device, Some(statics))
+                        }
+                        Err(e) => {
+                            let device = unreachable_device(e, cached.as_ref());
+                            let keep = matches_identity(filter, cached.as_ref())
+                                && filter.matches_activity(&device);
+                            (keep.then_some(device), cached.map(StaticInfo::into_stale))
+                        }
+                    });
+                    (index, handle)
+                })
+                .collect::<Vec<_>>();
+            handles
+                .into_iter()
+                .map(|(index, handle)| {
+                    let sampled = handle.join().expect("device sampling thread panicked");
+                    (index, sampled)
+                })
+                .collect::<Vec<_>>()
+        });
+        let mut devices = Vec::with_capacity(sampled.len());
+        for (index, (device, statics)) in sampled {
+            match statics {
+                Some(statics) => self.statics.insert(index, statics),
+                None => self.statics.remove(&index),
+            };
+            devices.extend(device);
+        }
+
+        if self.container_support {
+            let gpu_pids = devices
+                .iter()
+                .flat_map(|device| device.processes.iter().map(|proc| proc.pid))
+                .collect::<BTreeSet<_>>();
+            if self.ctr_map.is_none() || gpu_pids != self.gpu_pids {
+                self.ctr_map = Some(load_containers());
+                self.gpu_pids = gpu_pids;
+            }
+        }
+        let pd = ProcessData::load(&mut self.sys, &devices, self.ctr_map.clone());
+        let mut processes = pd.items().clone();
+        let now = Instant::now();
+        let mut cpu_times = HashMap::new();
+        for proc in processes.iter_mut() {
+            proc.user = self.names.user(proc.uid);
+            proc.group = self.names.group(proc.gid);
+            proc.ns_pids = self.procfs.ns_pids(proc.pid);
+            proc.owner = self.procfs.owner(proc.pid);
+            if let Some(ticks) = self.procfs.cpu_ticks(proc.pid) {
+                proc.cpu_percent = cpu_percent(proc, ticks, self.cpu_times.get(&proc.pid), now);
+                cpu_times.insert(proc.pid, (ticks, now));
+            }
+        }
+        self.cpu_times = cpu_times;
+        if let Some(slurm) = self.slurm.as_mut() {
+            let ids = processes
+                .iter()
+                .map(|proc| slurm::job_id(&self.procfs, proc.pid, proc.owner.as_ref()))
+                .collect::<Vec<_>>();
+            let jobs = slurm.jobs(&ids.iter().flatten().copied().collect::<Vec<_>>());
+            for (proc, id) in processes.iter_mut().zip(ids) {
+                proc.slurm_job = id.map(|id| jobs[&id].clone());
+            }
+        }
+        let pods = match self.kubelet.as_ref().map(PodResourcesClient::list) {
+            Some(Ok(pods)) => {
+                self.kubelet_failing = false;
+                kubelet::gpu_allocations(&pods)
+            }
+            Some(Err(e)) => {
+                // Once per outage rather than every sample.
+                if !self.kubelet_failing {
+                    eprintln!("gmon: kubelet: {}", e);
+                    self.kubelet_failing = true;
+                }
+                vec![]
+            }
+            None => vec![],
+        };
+        let by_uuid = kubelet::by_uuid(&pods);
+        let mut container_pods = HashMap::new();
+        for proc in processes.iter_mut() {
+            let (uid, container) = match (&self.kubelet, &proc.owner) {
+                (Some(_), Some(Owner::Pod { uid, container })) => (uid, container),
+                _ => continue,
+            };
+            // The runtime knows which container a process is in.
+            if let Some(id) = container {
+                proc.pod = container_pods
+                    .entry(id.clone())
+                    .or_insert_with(|| {
+                        self.container_pods
+                            .remove(id)
+                            .unwrap_or_else(|| kubelet::container_pod(uid, id))
+                    })
+                    .clone();
+                if proc.pod.is_some() {
+                    continue;
+                }
+            }
+            // Failing that, a process on a GPU allocated to one container
+            // only is in that container; host processes may use allocated
+            // GPUs too. MIG instances are allocated by their own UUIDs.
+            let refs = devices
+                .iter()
+                .filter(|device| device.index == proc.gpu_id)
+                .flat_map(|device| {
+                    device
+                        .uuid
+                        .iter()
+                        .map(|uuid| &uuid.val)
+                        .chain(&device.mig_uuids)
+                })
+                .filter_map(|uuid| by_uuid.get(uuid.as_str()))
+                .flatten()
+                .collect::<Vec<_>>();
+            proc.pod = match refs.split_first() {
+                Some((first, rest)) if rest.iter().all(|pod| pod == first) => {
+                    Some((**first).clone())
+                }
+                _ => None,
+            };
+        }
+        self.container_pods = container_pods;
+        if let Some(job) = self.slurm_job {
+            processes.retain(|proc| proc.slurm_job.as_ref().is_some_and(|j| j.id == job));
+            devices.retain(|device| processes.iter().any(|proc| proc.gpu_id == device.index));
+            for device in devices.iter_mut() {
+                device
+                    .processes
+                    .retain(|info| processes.iter().any(|proc| proc.pid == info.pid));
+            }
+        }
+        Ok(Snapshot {
+            timestamp,
+            host: self.host.clone(),
+            driver_version: self.driver_version.clone(),
+            cuda_version: self.cuda_version.clone(),
+            processes,
+            containers: pd.containers(),
+            pods,
+            claims: self
+                .claims
+                .as_ref()
+                .map(ClaimStore::load)
+                .unwrap_or_default(),
+            devices,
+        })
+    }
+}
@@ -0,0 +1,319 @@
+use gmon::{claim, kubelet};
+use owo_colors::AnsiColors;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+use crate::cli::{Args, Backend, Format};
+use crate::display::{self, color_by_name, Column, Scale};
+
+/// Read before the user's file, which overrides it key by key.
+const SYSTEM_PATH: &str = "/etc/gmon/config.toml";
+
+#[derive(Error, Debug)]
+pub enum ConfigError {
+    #[error("failed to read {0}: {1}")]
+    Read(PathBuf, std::io::Error),
+    #[error("failed to parse {0}: {1}")]
+    Parse(PathBuf, toml::de::Error),
+    #[error("invalid colour scale display.{0}: {1}")]
+    Scale(&'static str, String),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub backend: Backend,
+    pub container_support: bool,
+    /// Seconds between samples in watch mode and the TUI.
+    pub interval: f64,
+    /// Where process details are read from; a fixture tree can stand in for
+    /// `/proc`.
+    pub proc_root: PathBuf,
+    pub nvml: NvmlConfig,
+    pub display: DisplayConfig,
+    pub claims: ClaimsConfig,
+    pub queue: QueueConfig,
+    pub slurm: SlurmConfig,
+    pub kubernetes: KubernetesConfig,
+    /// Files the values were read from, in order.
+    #[serde(skip)]
+    pub sources: Vec<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            backend: Backend::Nvidia,
+            container_support: false,
+            interval: 1.0,
+            proc_root: PathBuf::from("/proc"),
+            nvml: NvmlConfig::default(),
+            display: DisplayConfig::default(),
+            claims: ClaimsConfig::default(),
+            queue: QueueConfig::default(),
+            slurm: SlurmConfig::default(),
+            kubernetes: KubernetesConfig::default(),
+            sources: vec![],
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NvmlConfig {
+    pub lib_paths: Vec<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ClaimsConfig {
+    /// Directory shared by everyone claiming GPUs on this host.
+    pub dir: PathBuf,
+}
+
+impl Default for ClaimsConfig {
+    fn default() -> Self {
+        ClaimsConfig {
+            dir: PathBuf::from(claim::DEFAULT_DIR),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct QueueConfig {
+    /// Spool of the user's queued jobs.
+    pub dir: PathBuf,
+}
+
+impl Default for QueueConfig {
+    fn default() -> Self {
+        let state_home = match std::env::var_os("XDG_STATE_HOME") {
+            Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+            _ => std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/state")),
+        };
+        let dir = match state_home {
+            Some(dir) => dir.join("gmon").join("queue"),
+            None => std::env::temp_dir().join(format!("gmon-queue-{}", unsafe { libc::getuid() })),
+        };
+        QueueConfig { dir }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SlurmConfig {
+    /// Attribute GPU processes to Slurm jobs.
+    pub enabled: bool,
+    /// Command job details are read from.
+    pub squeue: PathBuf,
+}
+
+impl Default for SlurmConfig {
+    fn default() -> Self {
+        SlurmConfig {
+            enabled: false,
+            squeue: PathBuf::from("squeue"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct KubernetesConfig {
+    /// Ask the kubelet which containers were allocated which GPUs.
+    pub enabled: bool,
+    /// The kubelet's pod-resources socket.
+    pub socket: PathBuf,
+}
+
+impl Default for KubernetesConfig {
+    fn default() -> Self {
+        KubernetesConfig {
+            enabled: false,
+            socket: PathBuf::from(kubelet::DEFAULT_SOCKET),
+        }
+    }
+}
+
+/// Layout and colours of the text output.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayConfig {
+    /// Line templates used when `--format`/`--process-format` are not given.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu_format: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub process_format: Option<String>,
+    /// Columns of the default table, in order.
+    pub columns: Vec<Column>,
+    pub temperature: ScaleConfig,
+    pub utilization: ScaleConfig,
+    pub memory: ScaleConfig,
+}
+
+impl Default for DisplayConfig {
+    fn default() -> Self {
+        let defaults = display::Settings::default();
+        DisplayConfig {
+            gpu_format: None,
+            process_format: None,
+            columns: defaults.columns,
+            temperature: ScaleConfig::from(&defaults.temperature),
+            utilization: ScaleConfig::from(&defaults.utilization),
+            memory: ScaleConfig::from(&defaults.memory),
+        }
+    }
+}
+
+/// `colors` has one entry more than `levels`: the colour up to the first
+/// level, then the colour above each level.
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScaleConfig {
+    pub levels: Vec<u32>,
+    pub colors: Vec<String>,
+}
+
+fn color_name(color: AnsiColors) -> &'static str {
+    match color {
+        AnsiColors::Black => "black",
+        AnsiColors::Red => "red",
+        AnsiColors::Green => "green",
+        AnsiColors::Yellow => "yellow",
+        AnsiColors::Blue => "blue",
+        AnsiColors::Magenta => "magenta",
+        AnsiColors::Cyan => "cyan",
+        _ => "white",
+    }
+}
+
+impl From<&Scale> for ScaleConfig {
+    fn from(scale: &Scale) -> Self {
+        ScaleConfig {
+            levels: scale.levels.clone(),
+            colors: scale.colors.iter().map(|c| color_name(*c).into()).collect(),
+        }
+    }
+}
+
+impl ScaleConfig {
+    fn to_scale(&self, name: &'static str) -> Result<Scale, ConfigError> {
+        let invalid = |msg: String| ConfigError::Scale(name, msg);
+        if self.colors.len() != self.levels.len() + 1 {
+            return Err(invalid(format!(
+                "{} levels need {} colors, got {}",
+                self.levels.len(),
+                self.levels.len() + 1,
+                self.colors.len()
+            )));
+        }
+        if self.levels.windows(2).any(|w| w[0] >= w[1]) {
+            return Err(invalid(String::from("levels must be increasing")));
+        }
+        let colors = self
+            .colors
+            .iter()
+            .map(|name| {
+                color_by_name(name).ok_or_else(|| invalid(format!("unknown colour \"{}\"", name)))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Scale {
+            levels: self.levels.clone(),
+            colors,
+        })
+    }
+}
+
+/// Overlays `top` onto `base`, descending into tables so a file can change
+/// one key of a section without repeating the rest.
+fn merge(base: &mut toml::Table, top: toml::Table) {
+    for (key, val) in top {
+        match (base.get_mut(&key), val) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(top)) => merge(base, top),
+            (_, val) => {
+                base.insert(key, val);
+            }
+        }
+    }
+}
+
+fn read(path: &Path) -> Result<toml::Table, ConfigError> {
+    let text =
+        std::fs::read_to_string(path).map_err(|e| ConfigError::Read(path.to_path_buf(), e))?;
+    let table: toml::Table =
+        toml::from_str(&text).map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?;
+    // Check each file on its own so errors name the file at fault.
+    toml::Value::Table(table.clone())
+        .try_into::<Config>()
+        .map_err(|e| ConfigError::Parse(path.to_path_buf(), e))?;
+    Ok(table)
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+            Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+            _ => PathBuf::from(std::env::var_os("HOME")?).join(".config"),
+        };
+        Some(config_home.join("gmon").join("config.toml"))
+    }
+
+    /// Loads the system file, then the user's file over it.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut merged = toml::Table::new();
+        let mut sources = vec![];
+        let paths = std::iter::once(PathBuf::from(SYSTEM_PATH)).chain(Self::path());
+        for path in paths.filter(|path| path.exists()) {
+            merge(&mut merged, read(&path)?);
+            sources.push(path);
+        }
+        let path = sources.last().cloned().unwrap_or_default();
+        let mut config = toml::Value::Table(merged)
+            .try_into::<Config>()
+            .map_err(|e| ConfigError::Parse(path, e))?;
+        config.sources = sources;
+        Ok(config)
+    }
+
+    /// Lets command-line flags take precedence over the files.
+    pub fn apply_args(&mut self, args: &Args) {
+        if let Some(backend) = args.backend {
+            self.backend = backend;
+        }
+        if args.container_support {
+            self.container_support = true;
+        }
+        if args.no_container_support {
+            self.container_support = false;
+        }
+        if args.slurm || args.slurm_job.is_some() {
+            self.slurm.enabled = true;
+        }
+        if args.kubernetes {
+            self.kubernetes.enabled = true;
+        }
+        if let Some(Some(secs)) = args.interval {
+            self.interval = secs;
+        }
+        if !args.nvml_lib.is_empty() {
+            self.nvml.lib_paths = args.nvml_lib.clone();
+        }
+        if let Some(Format::Template(template)) = &args.format {
+            self.display.gpu_format = Some(template.source().to_string());
+        }
+        if let Some(template) = &args.process_format {
+            self.display.process_format = Some(template.source().to_string());
+        }
+    }
+
+    pub fn display_settings(&self) -> Result<display::Settings, ConfigError> {
+        Ok(display::Settings {
+            columns: self.display.columns.clone(),
+            temperature: self.display.temperature.to_scale("temperature")?,
+            utilization: self.display.utilization.to_scale("utilization")?,
+            memory: self.display.memory.to_scale("memory")?,
+        })
+    }
+}
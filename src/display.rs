@@ -1,8 +1,12 @@
-use crate::nv_gpu;
-
 use chrono::{DateTime, Local};
+use gmon::snapshot::{DeviceStatus, Snapshot};
+use gmon::vendors::types::Value;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write;
+use std::sync::OnceLock;
+
 use owo_colors::colors as Colors;
-use owo_colors::{OwoColorize, Stream::Stdout, Style, Styled};
+use owo_colors::{AnsiColors, OwoColorize, Stream::Stdout, Style, Styled};
 use terminal_size::{terminal_size, Width};
 
 pub struct DF {
@@ -22,6 +26,9 @@ impl DF {
             pad_fill: ' ',
         }
     }
+    pub fn supports_color(&self) -> bool {
+        self.is_support_color
+    }
     pub fn try_style<'a, T: OwoColorize>(&self, input: &'a T, style: Style) -> Styled<&'a T> {
         if self.is_support_color {
             input.style(style)
@@ -30,18 +37,19 @@ impl DF {
             input.style(style)
         }
     }
-    pub fn padding(
+    #[allow(clippy::too_many_arguments)]
+    pub fn padding<T: ToString>(
         &self,
-        val: (&nv_gpu::Value, Option<Style>),
-        val_fmt: Option<fn(&nv_gpu::Value) -> String>,
+        val: (&Value<T>, Option<Style>),
+        val_fmt: Option<fn(&Value<T>) -> String>,
         prefix: (Option<&str>, Option<Style>),
         suffix: (Option<&str>, Option<Style>),
         width: usize,
         pad_position: Pad,
         pad_fill: Option<char>,
     ) -> String {
-        fn default_val_fmt(val: &nv_gpu::Value) -> String {
-            val.val_as_isize().to_string()
+        fn default_val_fmt<T: ToString>(val: &Value<T>) -> String {
+            val.val.to_string()
         }
         let prefix_str = prefix.0.unwrap_or("");
         let val_string = val_fmt.unwrap_or(default_val_fmt)(val.0);
@@ -60,15 +68,15 @@ impl DF {
             "{}{}{}",
             match prefix.1 {
                 Some(style) => format!("{}", self.try_style(&prefix_str, style)),
-                None => format!("{}", prefix_str),
+                None => prefix_str.to_string(),
             },
             match val.1 {
                 Some(style) => format!("{}", self.try_style(&val_string, style)),
-                None => format!("{}", val_string),
+                None => val_string,
             },
             match suffix.1 {
                 Some(style) => format!("{}", self.try_style(&suffix_str, style)),
-                None => format!("{}", suffix_str),
+                None => suffix_str.to_string(),
             },
         );
         if padding_size == 0 {
@@ -84,7 +92,7 @@ impl DF {
     }
 }
 
-fn get_width() -> usize {
+pub fn get_width() -> usize {
     let w = match terminal_size() {
         Some((Width(w), _)) => w,
         None => 60,
@@ -92,165 +100,406 @@ fn get_width() -> usize {
     w as usize
 }
 
-enum Pad {
-    Left,
-    Right,
+/// Colours for a reading: `colors[i]` applies above `levels[i - 1]`, and
+/// `colors[0]` up to and including `levels[0]`.
+#[derive(Debug, Clone)]
+pub struct Scale {
+    pub levels: Vec<u32>,
+    pub colors: Vec<AnsiColors>,
 }
 
-fn padding(
-    text: String,
-    pad: Pad,
-    pad_size: usize,
-    fill: Option<char>,
-    post_fmt: Option<fn(String) -> String>,
-) -> String {
-    let ori_len = text.chars().count();
-    let text = match post_fmt {
-        Some(fmt) => fmt(text),
-        None => text,
-    };
-    let size = (pad_size - ori_len).max(0);
-    if size == 0 {
-        text
-    } else {
-        let p = String::from_iter((0..size).map(|_| fill.unwrap_or(' ')));
-        match pad {
-            Pad::Left => format!("{}{}", p, text),
-            Pad::Right => format!("{}{}", text, p),
+impl Scale {
+    pub fn color(&self, val: u32) -> AnsiColors {
+        let above = self.levels.iter().filter(|level| val > **level).count();
+        self.colors[above.min(self.colors.len() - 1)]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Column {
+    Index,
+    Name,
+    Temperature,
+    Power,
+    Utilization,
+    Memory,
+    /// Who claimed the GPU, if anyone.
+    Claim,
+    /// Kubernetes containers the GPU was allocated to.
+    Pod,
+    /// What the processes on the GPU run under, after their control groups:
+    /// services, sessions, jobs, pods or containers.
+    Owner,
+    /// `user:command/pid(memory)` for each process, like gpustat, with the
+    /// PID inside its container in brackets and the Slurm job after `@`.
+    Processes,
+}
+
+impl Column {
+    pub const ALL: [Column; 10] = [
+        Column::Index,
+        Column::Name,
+        Column::Temperature,
+        Column::Power,
+        Column::Utilization,
+        Column::Memory,
+        Column::Claim,
+        Column::Pod,
+        Column::Owner,
+        Column::Processes,
+    ];
+}
+
+/// Columns and colour thresholds of the default table, set once from the
+/// config file.
+#[derive(Debug, Clone)]
+pub struct Settings {
+    pub columns: Vec<Column>,
+    pub temperature: Scale,
+    pub utilization: Scale,
+    pub memory: Scale,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            columns: Column::ALL.to_vec(),
+            temperature: Scale {
+                levels: vec![30, 50, 75],
+                colors: vec![
+                    AnsiColors::Blue,
+                    AnsiColors::Green,
+                    AnsiColors::Yellow,
+                    AnsiColors::Red,
+                ],
+            },
+            utilization: Scale {
+                levels: vec![50, 90],
+                colors: vec![AnsiColors::Green, AnsiColors::Yellow, AnsiColors::Red],
+            },
+            memory: Scale {
+                levels: vec![50, 90],
+                colors: vec![AnsiColors::Green, AnsiColors::Yellow, AnsiColors::Red],
+            },
         }
     }
 }
 
-pub fn print_header(info: &nv_gpu::GPUInfo) {
-    println!("width: ({})", get_width());
-    let dt_text = format!("{} {}", &info.timestamp, Local::now().format("%z"));
-    let dt = DateTime::parse_from_str(&dt_text, "%a %b %e %H:%M:%S %Y %z").unwrap();
-    let l = dt.format("%Y/%m/%d %H:%M:%S");
+static SETTINGS: OnceLock<Settings> = OnceLock::new();
+
+/// Replaces the default settings; only the first call has an effect.
+pub fn configure(settings: Settings) {
+    let _ = SETTINGS.set(settings);
+}
+
+pub fn settings() -> &'static Settings {
+    SETTINGS.get_or_init(Settings::default)
+}
+
+pub fn color_by_name(name: &str) -> Option<AnsiColors> {
+    Some(match name {
+        "black" => AnsiColors::Black,
+        "red" => AnsiColors::Red,
+        "green" => AnsiColors::Green,
+        "yellow" => AnsiColors::Yellow,
+        "blue" => AnsiColors::Blue,
+        "magenta" => AnsiColors::Magenta,
+        "cyan" => AnsiColors::Cyan,
+        "white" => AnsiColors::White,
+        _ => return None,
+    })
+}
+
+pub fn temperature_color(read: u32) -> AnsiColors {
+    settings().temperature.color(read)
+}
+
+pub fn usage_color(percent: u32) -> AnsiColors {
+    settings().utilization.color(percent)
+}
+
+pub fn memory_color(percent: u32) -> AnsiColors {
+    settings().memory.color(percent)
+}
+
+pub enum Pad {
+    Left,
+    Right,
+}
+
+pub fn print_header(snapshot: &Snapshot) {
+    println!(
+        "{}",
+        render_header(snapshot, &snapshot.timestamp, get_width())
+    );
+}
+
+pub fn render_header(snapshot: &Snapshot, clock: &DateTime<Local>, width: usize) -> String {
+    let l = clock.format("%Y/%m/%d %H:%M:%S").to_string();
+    let driver_version = match &snapshot.driver_version {
+        Some(version) => version.val.as_str(),
+        None => "N/A",
+    };
+    let cuda_version = match &snapshot.cuda_version {
+        Some(version) => format!("{}.{}", version.major.val, version.minor.val),
+        None => String::from("N/A"),
+    };
+    let r_len = driver_version.len() + " CUDA:".len() + cuda_version.len();
     let r = format!(
         "{} CUDA:{}",
-        info.driver_version,
-        info.cuda_version.fg::<Colors::Yellow>(),
-    );
-    println!(
-        "{}{}{}",
-        l,
-        String::from_iter((0..get_width() - 19 - 19).map(|_| ' ')),
-        r,
+        driver_version,
+        DF::new().try_style(&cuda_version, Style::new().fg::<Colors::Yellow>()),
     );
+    let gap = width.saturating_sub(l.len() + r_len).max(1);
+    format!("{}{}{}", l, String::from_iter((0..gap).map(|_| ' ')), r)
 }
 
-pub fn print_gpu_info_basic(info: &nv_gpu::GPUInfo) {
+pub fn render_gpu_info_basic(snapshot: &Snapshot) -> String {
     let df = DF::new();
-    let mut max_mem_str_len: Option<usize> = None;
-    for (i, gpu) in info.gpus.iter().enumerate() {
-        if max_mem_str_len.is_none() {
-            max_mem_str_len = Some(
-                format!(
-                    "{}",
-                    match gpu.fb_memory_usage.total.val {
-                        nv_gpu::ValidValue::INT(v) => {
-                            v
-                        }
-                        _ => 1000,
-                    }
-                )
-                .len(),
+    let mut out = String::new();
+    let na = Value::from(String::from("N/A"));
+    let max_mem_str_len = snapshot
+        .devices
+        .iter()
+        .filter_map(|gpu| gpu.memory.as_ref())
+        .map(|mem| mem.total.val.to_string().len())
+        .max()
+        .unwrap_or(4);
+    let max_name_len = snapshot
+        .devices
+        .iter()
+        .filter_map(|gpu| gpu.name.as_ref())
+        .map(|name| name.val.chars().count())
+        .max()
+        .unwrap_or(3);
+    for gpu in snapshot.devices.iter() {
+        let gpu_id = df.try_style(&gpu.index, Style::new().fg::<Colors::Yellow>());
+        let unreachable = match &gpu.status {
+            DeviceStatus::Available => None,
+            DeviceStatus::Lost { reason } => Some(format!("lost: {}", reason)),
+            DeviceStatus::Unavailable { reason } => Some(format!("unavailable: {}", reason)),
+        };
+        if let Some(text) = unreachable {
+            let _ = writeln!(
+                out,
+                "{} {}",
+                gpu_id,
+                df.try_style(&text, Style::new().fg::<Colors::Red>())
             );
+            continue;
         }
-        let gpu_id = df.try_style(&i, Style::new().fg::<Colors::Yellow>());
-        // let product_name
-        let temperature = {
-            let read = gpu.temperature.value.val_as_isize();
-            let style = Style::new();
-            let style = if read > 75 {
-                style.fg::<Colors::Red>()
-            } else if read > 50 {
-                style.fg::<Colors::Yellow>()
-            } else if read > 30 {
-                style.fg::<Colors::Green>()
-            } else {
-                style.fg::<Colors::Blue>()
-            };
-            df.padding(
-                (&gpu.temperature.value, Some(style)),
+        let product_name = df.padding(
+            (gpu.name.as_ref().unwrap_or(&na), None),
+            None,
+            (None, None),
+            (Some(""), None),
+            max_name_len,
+            Pad::Right,
+            None,
+        );
+        let temperature = match &gpu.temperature {
+            Some(temp) => {
+                let style = Style::new().color(temperature_color(temp.val));
+                df.padding(
+                    (temp, Some(style)),
+                    None,
+                    (None, None),
+                    (Some("°C"), Some(style)),
+                    3,
+                    Pad::Left,
+                    None,
+                )
+            }
+            None => df.padding(
+                (&na, None),
                 None,
                 (None, None),
-                (Some("°C"), Some(style)),
+                (None, None),
                 3,
                 Pad::Left,
                 None,
-            )
+            ),
         };
-        let power_draw = df.padding(
-            (&gpu.power_readings.draw, None),
-            None,
-            (None, None),
-            (Some(""), None),
-            3,
-            Pad::Left,
-            None,
-        );
-        let power_limit = {
-            if gpu
-                .power_readings
-                .limit
-                .val
-                .eq(&gpu.power_readings.default_limit.val)
-            {
-                String::from("")
-            } else {
+        let (power_draw, power_limit) = match &gpu.power {
+            Some(power) => {
+                fn watt(val: &Value<u32>) -> String {
+                    (val.val / 1000).to_string()
+                }
+                let power_draw = df.padding(
+                    (&power.read, None),
+                    Some(watt),
+                    (None, None),
+                    (Some(""), None),
+                    3,
+                    Pad::Left,
+                    None,
+                );
+                let power_limit = if power.limit.val.eq(&power.limit_default.val) {
+                    String::from("")
+                } else {
+                    df.padding(
+                        (&power.limit, Some(Style::new().fg::<Colors::Blue>())),
+                        Some(watt),
+                        (Some("/"), None),
+                        (Some(""), None),
+                        3,
+                        Pad::Left,
+                        None,
+                    )
+                };
+                (power_draw, power_limit)
+            }
+            None => (
+                df.padding(
+                    (&na, None),
+                    None,
+                    (None, None),
+                    (None, None),
+                    3,
+                    Pad::Left,
+                    None,
+                ),
+                String::from(""),
+            ),
+        };
+        let usage = match &gpu.utilization {
+            Some(util) => df.padding(
+                (util, Some(Style::new().color(usage_color(util.val)))),
+                None,
+                (None, None),
+                (Some(""), None),
+                3,
+                Pad::Left,
+                None,
+            ),
+            None => df.padding(
+                (&na, None),
+                None,
+                (None, None),
+                (None, None),
+                3,
+                Pad::Left,
+                None,
+            ),
+        };
+        let (mem_usage, mem_total) = match &gpu.memory {
+            Some(mem) => (
                 df.padding(
                     (
-                        &gpu.power_readings.limit,
-                        Some(Style::new().fg::<Colors::Blue>()),
+                        &mem.used,
+                        (mem.used.val * 100)
+                            .checked_div(mem.total.val)
+                            .map(|percent| Style::new().color(memory_color(percent as u32))),
                     ),
                     None,
                     (None, None),
                     (Some(""), None),
-                    3,
+                    max_mem_str_len,
                     Pad::Left,
                     None,
-                )
+                ),
+                df.padding(
+                    (&mem.total, None),
+                    None,
+                    (None, None),
+                    (Some(""), None),
+                    max_mem_str_len,
+                    Pad::Left,
+                    None,
+                ),
+            ),
+            None => (String::from("N/A"), String::from("N/A")),
+        };
+        let claim = snapshot
+            .claims
+            .iter()
+            .find(|claim| gpu.uuid.as_ref().is_some_and(|uuid| uuid.val == claim.uuid))
+            .map(|claim| {
+                let text = match &claim.note {
+                    Some(note) => format!("claimed by {} ({})", claim.user, note),
+                    None => format!("claimed by {}", claim.user),
+                };
+                df.try_style(&text, Style::new().fg::<Colors::Magenta>())
+                    .to_string()
+            })
+            .unwrap_or_default();
+        let pods = snapshot
+            .pods
+            .iter()
+            .filter(|alloc| gpu.uuid.as_ref().is_some_and(|uuid| uuid.val == alloc.uuid))
+            .map(|alloc| alloc.pod.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let pods = df
+            .try_style(&pods, Style::new().fg::<Colors::Cyan>())
+            .to_string();
+        let mut owners = vec![];
+        for owner in snapshot
+            .processes
+            .iter()
+            .filter(|proc| proc.gpu_id == gpu.index)
+            .filter_map(|proc| proc.owner.as_ref().map(|owner| owner.to_string()))
+        {
+            if !owners.contains(&owner) {
+                owners.push(owner);
             }
+        }
+        let owners = df
+            .try_style(&owners.join(","), Style::new().fg::<Colors::Green>())
+            .to_string();
+        let processes = snapshot
+            .processes
+            .iter()
+            .filter(|proc| proc.gpu_id == gpu.index)
+            .map(|proc| {
+                let memory = proc
+                    .gpu_info
+                    .used_memory
+                    .as_ref()
+                    .map_or_else(|| String::from("?"), |mem| mem.val.to_string());
+                let mut pid = match proc.container_pid() {
+                    Some(inner) => format!("{}[{}]", proc.pid, inner),
+                    None => proc.pid.to_string(),
+                };
+                if let Some(job) = &proc.slurm_job {
+                    pid += &format!("@{}", job.id);
+                }
+                format!(
+                    "{}:{}/{}({}M)",
+                    df.try_style(
+                        &proc.user_or_uid(),
+                        Style::new().fg::<Colors::BrightBlack>()
+                    ),
+                    df.try_style(&proc.name, Style::new().fg::<Colors::Blue>()),
+                    pid,
+                    df.try_style(&memory, Style::new().fg::<Colors::Yellow>()),
+                )
+            })
+            .collect::<Vec<_>>();
+        let processes = match processes.is_empty() {
+            true => String::new(),
+            false => format!("| {}", processes.join(" ")),
         };
-        let usage = df.padding(
-            (&gpu.utilization.gpu, None),
-            None,
-            (None, None),
-            (Some(""), None),
-            3,
-            Pad::Left,
-            None,
-        );
-        let mem_usage = df.padding(
-            (&gpu.fb_memory_usage.used, None),
-            None,
-            (None, None),
-            (Some(""), None),
-            max_mem_str_len.unwrap_or(4),
-            Pad::Left,
-            None,
-        );
-        let mem_total = df.padding(
-            (&gpu.fb_memory_usage.total, None),
-            None,
-            (None, None),
-            (Some(""), None),
-            max_mem_str_len.unwrap_or(4),
-            Pad::Left,
-            None,
-        );
 
-        println!(
-            "{} {} {} {}{}W {}% {}/{}MB",
-            gpu_id,
-            gpu.product_name,
-            temperature,
-            power_draw,
-            power_limit,
-            usage,
-            mem_usage,
-            mem_total,
-        );
+        let columns = settings()
+            .columns
+            .iter()
+            .map(|col| match col {
+                Column::Index => gpu_id.to_string(),
+                Column::Name => product_name.clone(),
+                Column::Temperature => temperature.clone(),
+                Column::Power => format!("{}{}W", power_draw, power_limit),
+                Column::Utilization => format!("{}%", usage),
+                Column::Memory => format!("{}/{}MiB", mem_usage, mem_total),
+                Column::Claim => claim.clone(),
+                Column::Pod => pods.clone(),
+                Column::Owner => owners.clone(),
+                Column::Processes => processes.clone(),
+            })
+            .filter(|col| !col.is_empty())
+            .collect::<Vec<_>>();
+        let _ = writeln!(out, "{}", columns.join(" "));
     }
+    out
 }
@@ -1,13 +1,16 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
 use crate::docker::docker::Id;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct Container {
     pub id: Id,
     pub name: String,
     pub state: State,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct State {
     pub pid: u32,
 }
@@ -10,7 +10,7 @@ fn exec(args: Vec<&str>) -> Result<String, Error> {
 pub type Id = String;
 pub type ShortId = String;
 
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct Docker {
     // containerd_shim_pid: u32
 }
@@ -76,7 +76,7 @@ impl Docker {
                 return vec![];
             }
             Err(e) => {
-                println!("{:?}", e);
+                eprintln!("gmon: docker: {}", e);
                 return vec![];
             }
         };
@@ -96,7 +96,7 @@ impl Docker {
         let out_arr: Vec<&str> = out.split('\n').collect();
         let mut ctr_arr: Vec<container::Container> = vec![];
         for out in out_arr {
-            if out.len() == 0 {
+            if out.is_empty() {
                 continue;
             }
             ctr_arr.push(self.load_ctr_from_inspect(out))
@@ -1,4 +1,5 @@
 pub mod container;
+#[allow(clippy::module_inception)]
 mod docker;
 
 pub use docker::Docker;
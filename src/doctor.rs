@@ -0,0 +1,76 @@
+use gmon::kubelet::{gpu_allocations, PodResourcesClient};
+use gmon::vendors::nvidia::{device_nodes, NvGpuProvider};
+use gmon::vendors::traits::{GpuInfo, GpuProviderInfo};
+
+pub fn run(lib_candidates: &[String], kubelet: Option<&PodResourcesClient>) {
+    if let Some(client) = kubelet {
+        match client.list() {
+            Ok(pods) => println!(
+                "Kubelet: {} pods, {} GPU allocations",
+                pods.len(),
+                gpu_allocations(&pods).len()
+            ),
+            Err(e) => println!("Kubelet: {}", e),
+        }
+    }
+    let (provider, attempts) = NvGpuProvider::probe(lib_candidates);
+    println!("NVML library candidates:");
+    for attempt in attempts.iter() {
+        match &attempt.error {
+            Some(e) => println!("  {}: {}", attempt.candidate, e),
+            None => println!("  {}: ok", attempt.candidate),
+        }
+    }
+
+    let nodes = device_nodes();
+    println!("Device nodes:");
+    if nodes.is_empty() {
+        println!(
+            "  no /dev/nvidia* nodes found, is the GPU passed through to this host or container?"
+        );
+    }
+    for node in nodes.iter() {
+        match &node.error {
+            Some(e) => println!("  {}: {}", node.path.display(), e),
+            None => println!("  {}: ok", node.path.display()),
+        }
+    }
+
+    let provider = match provider {
+        Ok(provider) => provider,
+        Err(e) => {
+            println!("NVML: {}", e);
+            return;
+        }
+    };
+    println!("NVML: loaded from {}", provider.library());
+    match provider.nvml_version() {
+        Ok(version) => println!("NVML version: {}", version.val),
+        Err(e) => println!("NVML version: {}", e),
+    }
+    match provider.driver_version() {
+        Ok(version) => println!("Driver version: {}", version.val),
+        Err(e) => println!("Driver version: {}", e),
+    }
+    match provider.cuda_version() {
+        Ok(version) => println!("CUDA version: {}.{}", version.major.val, version.minor.val),
+        Err(e) => println!("CUDA version: {}", e),
+    }
+    match provider.device_count() {
+        Ok(count) => println!("Devices: {}", count.val),
+        Err(e) => println!("Devices: {}", e),
+    }
+    let gpus = match provider.gpu_iter() {
+        Ok(gpus) => gpus,
+        Err(_) => return,
+    };
+    for (i, gpu) in gpus.enumerate() {
+        match gpu {
+            Ok(gpu) => match gpu.name() {
+                Ok(name) => println!("  {}: {}", i, name.val),
+                Err(e) => println!("  {}: {}", i, e),
+            },
+            Err(e) => println!("  {}: {}", e.index, e.error),
+        }
+    }
+}
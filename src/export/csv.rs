@@ -0,0 +1,405 @@
+use thiserror::Error;
+
+use crate::process::GProcess;
+use crate::snapshot::{Device, Snapshot};
+
+#[derive(Error, Debug)]
+pub enum QueryError {
+    #[error("unknown field \"{field}\", available fields: {available}")]
+    UnknownField { field: String, available: String },
+    #[error("no fields requested")]
+    Empty,
+}
+
+/// How rows are written, following nvidia-smi's `--format=csv,noheader,nounits`.
+#[derive(Debug, Clone)]
+pub struct CsvOptions {
+    pub header: bool,
+    pub units: bool,
+    pub separator: &'static str,
+}
+
+impl Default for CsvOptions {
+    fn default() -> Self {
+        CsvOptions {
+            header: true,
+            units: true,
+            separator: ", ",
+        }
+    }
+}
+
+const NA: &str = "[N/A]";
+
+struct Field<T: ?Sized> {
+    names: &'static [&'static str],
+    unit: Option<&'static str>,
+    get: fn(&T, &Snapshot) -> Option<String>,
+}
+
+fn timestamp(snapshot: &Snapshot) -> Option<String> {
+    Some(
+        snapshot
+            .timestamp
+            .format("%Y/%m/%d %H:%M:%S%.3f")
+            .to_string(),
+    )
+}
+
+/// Quotes `val` if it contains the separator, a quote or a line break, so
+/// process names cannot shift columns.
+fn quote(val: String, separator: &str) -> String {
+    let sep = separator.trim_matches(' ');
+    if (!sep.is_empty() && val.contains(sep)) || val.contains(['"', '\n', '\r']) {
+        format!("\"{}\"", val.replace('"', "\"\""))
+    } else {
+        val
+    }
+}
+
+fn watts(mw: u32) -> String {
+    format!("{:.2}", mw as f64 / 1000.0)
+}
+
+static GPU_FIELDS: &[Field<Device>] = &[
+    Field {
+        names: &["timestamp"],
+        unit: None,
+        get: |_, s| timestamp(s),
+    },
+    Field {
+        names: &["driver_version"],
+        unit: None,
+        get: |_, s| s.driver_version.as_ref().map(|v| v.val.clone()),
+    },
+    Field {
+        names: &["count"],
+        unit: None,
+        get: |_, s| Some(s.devices.len().to_string()),
+    },
+    Field {
+        names: &["index"],
+        unit: None,
+        get: |d, _| Some(d.index.to_string()),
+    },
+    Field {
+        names: &["name", "gpu_name"],
+        unit: None,
+        get: |d, _| d.name.as_ref().map(|v| v.val.clone()),
+    },
+    Field {
+        names: &["uuid", "gpu_uuid"],
+        unit: None,
+        get: |d, _| d.uuid.as_ref().map(|v| v.val.clone()),
+    },
+    Field {
+        names: &["pci.bus_id", "gpu_bus_id"],
+        unit: None,
+        get: |d, _| d.pci_bus_id.as_ref().map(|v| v.val.clone()),
+    },
+    Field {
+        names: &["temperature.gpu"],
+        unit: None,
+        get: |d, _| d.temperature.as_ref().map(|v| v.val.to_string()),
+    },
+    Field {
+        names: &["utilization.gpu"],
+        unit: Some("%"),
+        get: |d, _| d.utilization.as_ref().map(|v| v.val.to_string()),
+    },
+    Field {
+        names: &["memory.total"],
+        unit: Some("MiB"),
+        get: |d, _| d.memory.as_ref().map(|m| m.total.val.to_string()),
+    },
+    Field {
+        names: &["memory.used"],
+        unit: Some("MiB"),
+        get: |d, _| d.memory.as_ref().map(|m| m.used.val.to_string()),
+    },
+    Field {
+        names: &["memory.free"],
+        unit: Some("MiB"),
+        get: |d, _| {
+            d.memory
+                .as_ref()
+                .map(|m| m.total.val.saturating_sub(m.used.val).to_string())
+        },
+    },
+    Field {
+        names: &["power.draw"],
+        unit: Some("W"),
+        get: |d, _| d.power.as_ref().map(|p| watts(p.read.val)),
+    },
+    Field {
+        names: &["power.limit", "enforced.power.limit"],
+        unit: Some("W"),
+        get: |d, _| d.power.as_ref().map(|p| watts(p.limit.val)),
+    },
+    Field {
+        names: &["power.default_limit"],
+        unit: Some("W"),
+        get: |d, _| d.power.as_ref().map(|p| watts(p.limit_default.val)),
+    },
+    Field {
+        names: &["clocks.current.graphics", "clocks.gr"],
+        unit: Some("MHz"),
+        get: |d, _| d.clocks.as_ref().map(|c| c.graphics.val.to_string()),
+    },
+    Field {
+        names: &["clocks.current.sm", "clocks.sm"],
+        unit: Some("MHz"),
+        get: |d, _| d.clocks.as_ref().map(|c| c.sm.val.to_string()),
+    },
+    Field {
+        names: &["clocks.current.memory", "clocks.mem"],
+        unit: Some("MHz"),
+        get: |d, _| d.clocks.as_ref().map(|c| c.memory.val.to_string()),
+    },
+    Field {
+        names: &["ecc.errors.corrected.volatile.total"],
+        unit: None,
+        get: |d, _| {
+            d.counters
+                .as_ref()
+                .and_then(|c| c.ecc_corrected.as_ref())
+                .map(|v| v.val.to_string())
+        },
+    },
+    Field {
+        names: &["ecc.errors.uncorrected.volatile.total"],
+        unit: None,
+        get: |d, _| {
+            d.counters
+                .as_ref()
+                .and_then(|c| c.ecc_uncorrected.as_ref())
+                .map(|v| v.val.to_string())
+        },
+    },
+];
+
+fn device_of<'a>(proc: &GProcess, snapshot: &'a Snapshot) -> Option<&'a Device> {
+    snapshot.devices.iter().find(|d| d.index == proc.gpu_id)
+}
+
+static APP_FIELDS: &[Field<GProcess>] = &[
+    Field {
+        names: &["timestamp"],
+        unit: None,
+        get: |_, s| timestamp(s),
+    },
+    Field {
+        names: &["gpu_index"],
+        unit: None,
+        get: |p, _| Some(p.gpu_id.to_string()),
+    },
+    Field {
+        names: &["gpu_name"],
+        unit: None,
+        get: |p, s| device_of(p, s).and_then(|d| d.name.as_ref().map(|v| v.val.clone())),
+    },
+    Field {
+        names: &["gpu_uuid"],
+        unit: None,
+        get: |p, s| device_of(p, s).and_then(|d| d.uuid.as_ref().map(|v| v.val.clone())),
+    },
+    Field {
+        names: &["gpu_bus_id"],
+        unit: None,
+        get: |p, s| device_of(p, s).and_then(|d| d.pci_bus_id.as_ref().map(|v| v.val.clone())),
+    },
+    Field {
+        names: &["pid"],
+        unit: None,
+        get: |p, _| Some(p.pid.to_string()),
+    },
+    Field {
+        names: &["process_name", "name"],
+        unit: None,
+        get: |p, _| Some(p.name.clone()),
+    },
+    Field {
+        names: &["used_gpu_memory", "used_memory"],
+        unit: Some("MiB"),
+        get: |p, _| p.gpu_info.used_memory.as_ref().map(|m| m.val.to_string()),
+    },
+    Field {
+        names: &["uid"],
+        unit: None,
+        get: |p, _| Some(p.uid.to_string()),
+    },
+    Field {
+        names: &["container"],
+        unit: None,
+        get: |p, s| p.container(&s.containers).map(|c| c.name.clone()),
+    },
+    Field {
+        names: &["container_id"],
+        unit: None,
+        get: |p, s| p.container(&s.containers).map(|c| c.id.clone()),
+    },
+    Field {
+        names: &["container_pid"],
+        unit: None,
+        get: |p, _| p.container_pid().map(|pid| pid.to_string()),
+    },
+    Field {
+        names: &["slurm_job_id"],
+        unit: None,
+        get: |p, _| p.slurm_job.as_ref().map(|job| job.id.to_string()),
+    },
+    Field {
+        names: &["slurm_job_name"],
+        unit: None,
+        get: |p, _| p.slurm_job.as_ref().and_then(|job| job.name.clone()),
+    },
+    Field {
+        names: &["pod"],
+        unit: None,
+        get: |p, _| p.pod.as_ref().map(|pod| pod.to_string()),
+    },
+    Field {
+        names: &["owner"],
+        unit: None,
+        get: |p, _| p.owner.as_ref().map(|owner| owner.to_string()),
+    },
+];
+
+fn resolve<T>(fields: &[Field<T>], query: &str) -> Result<Vec<(String, usize)>, QueryError> {
+    let resolved = query
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(|name| {
+            fields
+                .iter()
+                .position(|f| f.names.contains(&name))
+                .map(|i| (name.to_string(), i))
+                .ok_or_else(|| QueryError::UnknownField {
+                    field: name.to_string(),
+                    available: fields
+                        .iter()
+                        .map(|f| f.names[0])
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    if resolved.is_empty() {
+        return Err(QueryError::Empty);
+    }
+    Ok(resolved)
+}
+
+fn render<T>(
+    snapshot: &Snapshot,
+    items: &[T],
+    table: &[Field<T>],
+    fields: &[(String, usize)],
+    opts: &CsvOptions,
+) -> String {
+    let mut out = String::new();
+    if opts.header {
+        let header = fields
+            .iter()
+            .map(|(name, i)| match table[*i].unit {
+                Some(unit) => format!("{} [{}]", name, unit),
+                None => name.clone(),
+            })
+            .collect::<Vec<_>>();
+        out.push_str(&header.join(opts.separator));
+        out.push('\n');
+    }
+    for item in items {
+        let row = fields
+            .iter()
+            .map(|(_, i)| {
+                let field = &table[*i];
+                let val = match ((field.get)(item, snapshot), field.unit) {
+                    (Some(val), Some(unit)) if opts.units => format!("{} {}", val, unit),
+                    (Some(val), _) => val,
+                    (None, _) => NA.to_string(),
+                };
+                quote(val, opts.separator)
+            })
+            .collect::<Vec<_>>();
+        out.push_str(&row.join(opts.separator));
+        out.push('\n');
+    }
+    out
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Target {
+    Gpu,
+    ComputeApps,
+}
+
+/// A validated list of fields, in the order they were asked for.
+///
+/// ```
+/// use gmon::export::csv::{CsvOptions, Query};
+/// use gmon::vendors::fake::FakeGpuProvider;
+/// use gmon::Snapshot;
+///
+/// let query = Query::gpu("index,memory.used")?;
+/// let snapshot = Snapshot::collect(&FakeGpuProvider::with_idle_devices(2), false)?;
+/// let out = query.render(&snapshot, &CsvOptions::default());
+/// assert_eq!(out, "index, memory.used [MiB]\n0, 0 MiB\n1, 0 MiB\n");
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Query {
+    target: Target,
+    fields: Vec<(String, usize)>,
+}
+
+impl Query {
+    /// One row per device, like `nvidia-smi --query-gpu=...`.
+    pub fn gpu(query: &str) -> Result<Self, QueryError> {
+        Ok(Query {
+            target: Target::Gpu,
+            fields: resolve(GPU_FIELDS, query)?,
+        })
+    }
+
+    /// One row per GPU process, like `nvidia-smi --query-compute-apps=...`,
+    /// plus gmon's `uid`, `container`, `container_id`, `container_pid`,
+    /// `slurm_job_id`, `slurm_job_name`, `pod` and `owner` fields.
+    pub fn compute_apps(query: &str) -> Result<Self, QueryError> {
+        Ok(Query {
+            target: Target::ComputeApps,
+            fields: resolve(APP_FIELDS, query)?,
+        })
+    }
+
+    pub fn render(&self, snapshot: &Snapshot, opts: &CsvOptions) -> String {
+        match self.target {
+            Target::Gpu => render(snapshot, &snapshot.devices, GPU_FIELDS, &self.fields, opts),
+            Target::ComputeApps => render(
+                snapshot,
+                &snapshot.processes,
+                APP_FIELDS,
+                &self.fields,
+                opts,
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quote;
+
+    #[test]
+    fn quotes_values_containing_the_separator() {
+        assert_eq!(quote(String::from("python"), ", "), "python");
+        assert_eq!(quote(String::from("a,b"), ", "), "\"a,b\"");
+        assert_eq!(quote(String::from("a,b"), "\t"), "a,b");
+        assert_eq!(quote(String::from("a\tb"), "\t"), "\"a\tb\"");
+        assert_eq!(
+            quote(String::from("say \"hi\""), ", "),
+            "\"say \"\"hi\"\"\""
+        );
+    }
+}
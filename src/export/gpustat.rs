@@ -0,0 +1,175 @@
+use serde::Serialize;
+use serde_json::ser::PrettyFormatter;
+
+use crate::cgroup::Owner;
+use crate::kubelet::PodRef;
+use crate::process::GProcess;
+use crate::slurm::SlurmJob;
+use crate::snapshot::{Device, Snapshot};
+
+/// The document `gpustat --json` prints, so its consumers can read gmon
+/// output unchanged. Values gmon does not sample are `null`.
+#[derive(Debug, Serialize)]
+pub struct Document {
+    pub hostname: String,
+    pub driver_version: Option<String>,
+    pub query_time: String,
+    pub gpus: Vec<Gpu>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Gpu {
+    pub index: u32,
+    pub uuid: Option<String>,
+    pub name: Option<String>,
+    #[serde(rename = "temperature.gpu")]
+    pub temperature: Option<u32>,
+    #[serde(rename = "fan.speed")]
+    pub fan_speed: Option<u32>,
+    #[serde(rename = "utilization.gpu")]
+    pub utilization: Option<u32>,
+    #[serde(rename = "utilization.enc")]
+    pub utilization_enc: Option<u32>,
+    #[serde(rename = "utilization.dec")]
+    pub utilization_dec: Option<u32>,
+    /// Watts, truncated like gpustat does.
+    #[serde(rename = "power.draw")]
+    pub power_draw: Option<u32>,
+    #[serde(rename = "enforced.power.limit")]
+    pub power_limit: Option<u32>,
+    /// MiB.
+    #[serde(rename = "memory.used")]
+    pub memory_used: Option<u64>,
+    #[serde(rename = "memory.total")]
+    pub memory_total: Option<u64>,
+    pub processes: Vec<Process>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Process {
+    pub username: String,
+    pub command: String,
+    pub full_command: Option<Vec<String>>,
+    pub gpu_memory_usage: Option<u64>,
+    pub cpu_percent: Option<f32>,
+    pub cpu_memory_usage: Option<u64>,
+    pub pid: u32,
+    /// Fields gpustat does not have.
+    pub gmon: Extension,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Extension {
+    pub uid: u32,
+    pub gid: u32,
+    pub container: Option<ContainerRef>,
+    /// PIDs in the nested PID namespaces, the host's first.
+    pub ns_pids: Vec<u32>,
+    pub owner: Option<Owner>,
+    pub slurm_job: Option<SlurmJob>,
+    pub pod: Option<PodRef>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ContainerRef {
+    pub id: String,
+    pub name: String,
+}
+
+impl Process {
+    fn new(proc: &GProcess, snapshot: &Snapshot) -> Self {
+        Process {
+            username: proc.user_or_uid(),
+            command: proc.name.clone(),
+            full_command: Some(proc.cmdline.clone()).filter(|cmd| !cmd.is_empty()),
+            gpu_memory_usage: proc.gpu_info.used_memory.as_ref().map(|m| m.val),
+            cpu_percent: proc.cpu_percent,
+            cpu_memory_usage: proc.rss,
+            pid: proc.pid,
+            gmon: Extension {
+                uid: proc.uid,
+                gid: proc.gid,
+                container: proc
+                    .container(&snapshot.containers)
+                    .map(|ctr| ContainerRef {
+                        id: ctr.id.clone(),
+                        name: ctr.name.clone(),
+                    }),
+                ns_pids: proc.ns_pids.clone(),
+                owner: proc.owner.clone(),
+                slurm_job: proc.slurm_job.clone(),
+                pod: proc.pod.clone(),
+            },
+        }
+    }
+}
+
+impl Gpu {
+    fn new(device: &Device, snapshot: &Snapshot) -> Self {
+        Gpu {
+            index: device.index,
+            uuid: device.uuid.as_ref().map(|v| v.val.clone()),
+            name: device.name.as_ref().map(|v| v.val.clone()),
+            temperature: device.temperature.as_ref().map(|v| v.val),
+            fan_speed: None,
+            utilization: device.utilization.as_ref().map(|v| v.val),
+            utilization_enc: None,
+            utilization_dec: None,
+            power_draw: device.power.as_ref().map(|p| p.read.val / 1000),
+            power_limit: device.power.as_ref().map(|p| p.limit.val / 1000),
+            memory_used: device.memory.as_ref().map(|m| m.used.val),
+            memory_total: device.memory.as_ref().map(|m| m.total.val),
+            processes: snapshot
+                .processes
+                .iter()
+                .filter(|proc| proc.gpu_id == device.index)
+                .map(|proc| Process::new(proc, snapshot))
+                .collect(),
+        }
+    }
+}
+
+impl Document {
+    pub fn new(snapshot: &Snapshot) -> Self {
+        Document {
+            hostname: snapshot.host.clone(),
+            driver_version: snapshot.driver_version.as_ref().map(|v| v.val.clone()),
+            query_time: snapshot
+                .timestamp
+                .naive_local()
+                .format("%Y-%m-%dT%H:%M:%S%.6f")
+                .to_string(),
+            gpus: snapshot
+                .devices
+                .iter()
+                .map(|device| Gpu::new(device, snapshot))
+                .collect(),
+        }
+    }
+}
+
+/// Pretty-printed with gpustat's four-space indent.
+///
+/// ```
+/// use gmon::export::gpustat;
+/// use gmon::vendors::fake::FakeGpuProvider;
+/// use gmon::Snapshot;
+///
+/// let snapshot = Snapshot::collect(&FakeGpuProvider::with_idle_devices(2), false)?;
+/// let value: serde_json::Value = serde_json::from_str(&gpustat::to_string(&snapshot)?)?;
+/// assert_eq!(value["gpus"][1]["index"], 1);
+/// assert_eq!(value["gpus"][1]["memory.used"], 0);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn to_string(snapshot: &Snapshot) -> serde_json::Result<String> {
+    let mut buf = Vec::new();
+    let mut ser =
+        serde_json::Serializer::with_formatter(&mut buf, PrettyFormatter::with_indent(b"    "));
+    Document::new(snapshot).serialize(&mut ser)?;
+    Ok(String::from_utf8(buf).expect("serde_json writes UTF-8"))
+}
+
+/// Single-line document, for streaming one snapshot per line.
+pub fn to_line(snapshot: &Snapshot) -> serde_json::Result<String> {
+    serde_json::to_string(&Document::new(snapshot))
+}
@@ -0,0 +1,113 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::snapshot::Snapshot;
+
+/// Bumped whenever a field is renamed, removed or changes meaning. Adding
+/// fields does not bump it.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// The document emitted by `gmon --json`: a snapshot tagged with the schema
+/// version it follows.
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct Document<'a> {
+    pub schema_version: u32,
+    #[serde(flatten)]
+    pub snapshot: &'a Snapshot,
+}
+
+impl<'a> Document<'a> {
+    pub fn new(snapshot: &'a Snapshot) -> Self {
+        Document {
+            schema_version: SCHEMA_VERSION,
+            snapshot,
+        }
+    }
+}
+
+/// Pretty-printed document.
+///
+/// Consumers can deserialize it straight into a [`Snapshot`].
+///
+/// ```
+/// use gmon::export::json;
+/// use gmon::vendors::fake::FakeGpuProvider;
+/// use gmon::Snapshot;
+///
+/// let snapshot = Snapshot::collect(&FakeGpuProvider::with_idle_devices(1), false)?;
+/// let text = json::to_string(&snapshot)?;
+/// let parsed: Snapshot = serde_json::from_str(&text)?;
+/// assert_eq!(parsed.devices.len(), 1);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn to_string(snapshot: &Snapshot) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(&Document::new(snapshot))
+}
+
+/// Single-line document, for streaming one snapshot per line.
+pub fn to_line(snapshot: &Snapshot) -> serde_json::Result<String> {
+    serde_json::to_string(&Document::new(snapshot))
+}
+
+/// JSON Schema describing [`Document`].
+pub fn schema() -> String {
+    let schema = schemars::schema_for!(Document<'static>);
+    serde_json::to_string_pretty(&schema).expect("schema is always serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Snapshot;
+
+    /// A document as written by the first release of schema version 1.
+    const V1: &str = r#"{
+  "schema_version": 1,
+  "timestamp": "2026-10-18T12:00:00+00:00",
+  "host": "node1",
+  "driver_version": { "val": "535.104.05", "unit": null },
+  "cuda_version": { "major": { "val": 12, "unit": null }, "minor": { "val": 2, "unit": null } },
+  "devices": [
+    {
+      "index": 0,
+      "status": { "state": "available" },
+      "uuid": { "val": "GPU-00000000-0000-0000-0000-000000000000", "unit": null },
+      "name": { "val": "NVIDIA A100-SXM4-80GB", "unit": null },
+      "utilization": { "val": 97, "unit": null },
+      "temperature": { "val": 61, "unit": "°C" },
+      "power": null,
+      "memory": {
+        "total": { "val": 81920, "unit": "MiB" },
+        "used": { "val": 40000, "unit": "MiB" }
+      },
+      "clocks": null,
+      "counters": null,
+      "processes": [{ "pid": 4242, "used_memory": { "val": 39000, "unit": "MiB" } }]
+    }
+  ],
+  "processes": [
+    {
+      "gpu_id": 0,
+      "pid": 4242,
+      "uid": 1000,
+      "gid": 1000,
+      "ctr_pid": null,
+      "name": "python",
+      "gpu_info": { "pid": 4242, "used_memory": { "val": 39000, "unit": "MiB" } }
+    }
+  ],
+  "containers": []
+}"#;
+
+    #[test]
+    fn reads_first_v1_documents() {
+        let snapshot: Snapshot = serde_json::from_str(V1).unwrap();
+        assert_eq!(snapshot.devices[0].pci_bus_id, None);
+        let proc = &snapshot.processes[0];
+        assert_eq!(proc.name, "python");
+        assert!(proc.ns_pids.is_empty());
+        assert!(proc.cmdline.is_empty());
+        assert_eq!(proc.owner, None);
+        assert!(snapshot.claims.is_empty());
+        assert!(snapshot.pods.is_empty());
+    }
+}
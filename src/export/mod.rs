@@ -0,0 +1,6 @@
+//! Machine-readable renderings of a [`Snapshot`](crate::Snapshot).
+
+pub mod csv;
+pub mod gpustat;
+pub mod json;
+pub mod prometheus;
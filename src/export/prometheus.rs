@@ -0,0 +1,249 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+use crate::snapshot::Snapshot;
+
+const MIB: u64 = 1024 * 1024;
+
+/// Content type of the text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+fn escape(val: &str) -> String {
+    val.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn labels(pairs: &[(&str, &str)]) -> String {
+    let inner = pairs
+        .iter()
+        .map(|(key, val)| format!("{}=\"{}\"", key, escape(val)))
+        .collect::<Vec<_>>()
+        .join(",");
+    format!("{{{}}}", inner)
+}
+
+/// Collects samples per metric so each family is written once with its
+/// HELP and TYPE lines, whatever order samples are added in.
+struct Families {
+    families: Vec<(&'static str, &'static str, &'static str, Vec<String>)>,
+}
+
+impl Families {
+    fn new() -> Self {
+        Families { families: vec![] }
+    }
+
+    fn add(
+        &mut self,
+        name: &'static str,
+        kind: &'static str,
+        help: &'static str,
+        labels: String,
+        val: f64,
+    ) {
+        let line = format!("{}{} {}", name, labels, val);
+        match self.families.iter_mut().find(|f| f.0 == name) {
+            Some(family) => family.3.push(line),
+            None => self.families.push((name, kind, help, vec![line])),
+        }
+    }
+
+    fn finish(self) -> String {
+        let mut out = String::new();
+        for (name, kind, help, lines) in self.families {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} {}", name, kind);
+            for line in lines {
+                out.push_str(&line);
+                out.push('\n');
+            }
+        }
+        out
+    }
+}
+
+/// Renders a snapshot in the Prometheus text exposition format.
+///
+/// Devices are labelled by `gpu` index and `uuid`. Process memory carries
+/// `pid`, `command`, `user` and `container` labels, and is also summed per
+/// container and per user.
+///
+/// ```
+/// use gmon::export::prometheus;
+/// use gmon::vendors::fake::FakeGpuProvider;
+/// use gmon::Snapshot;
+///
+/// let snapshot = Snapshot::collect(&FakeGpuProvider::with_idle_devices(1), false)?;
+/// let text = prometheus::render(&snapshot);
+/// assert!(text.contains("# TYPE gmon_gpu_temperature_celsius gauge"));
+/// assert!(text.contains(
+///     "gmon_gpu_temperature_celsius{gpu=\"0\",uuid=\"GPU-00000000-0000-0000-0000-000000000000\"} 30"
+/// ));
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub fn render(snapshot: &Snapshot) -> String {
+    let mut m = Families::new();
+    for device in snapshot.devices.iter() {
+        let index = device.index.to_string();
+        let uuid = device.uuid.as_ref().map_or("", |v| v.val.as_str());
+        let name = device.name.as_ref().map_or("", |v| v.val.as_str());
+        let dev = labels(&[("gpu", &index), ("uuid", uuid)]);
+        m.add(
+            "gmon_gpu_info",
+            "gauge",
+            "GPU identity, always 1.",
+            labels(&[("gpu", &index), ("uuid", uuid), ("name", name)]),
+            1.0,
+        );
+        m.add(
+            "gmon_gpu_up",
+            "gauge",
+            "Whether the GPU answered queries.",
+            dev.clone(),
+            if device.is_available() { 1.0 } else { 0.0 },
+        );
+        if let Some(util) = &device.utilization {
+            m.add(
+                "gmon_gpu_utilization_percent",
+                "gauge",
+                "GPU utilization.",
+                dev.clone(),
+                util.val as f64,
+            );
+        }
+        if let Some(temp) = &device.temperature {
+            m.add(
+                "gmon_gpu_temperature_celsius",
+                "gauge",
+                "GPU temperature.",
+                dev.clone(),
+                temp.val as f64,
+            );
+        }
+        if let Some(mem) = &device.memory {
+            m.add(
+                "gmon_gpu_memory_used_bytes",
+                "gauge",
+                "GPU memory in use.",
+                dev.clone(),
+                (mem.used.val * MIB) as f64,
+            );
+            m.add(
+                "gmon_gpu_memory_total_bytes",
+                "gauge",
+                "Total GPU memory.",
+                dev.clone(),
+                (mem.total.val * MIB) as f64,
+            );
+        }
+        if let Some(power) = &device.power {
+            m.add(
+                "gmon_gpu_power_watts",
+                "gauge",
+                "GPU power draw.",
+                dev.clone(),
+                power.read.val as f64 / 1000.0,
+            );
+            m.add(
+                "gmon_gpu_power_limit_watts",
+                "gauge",
+                "Enforced GPU power limit.",
+                dev.clone(),
+                power.limit.val as f64 / 1000.0,
+            );
+        }
+        if let Some(clocks) = &device.clocks {
+            for (clock, val) in [
+                ("graphics", &clocks.graphics),
+                ("sm", &clocks.sm),
+                ("memory", &clocks.memory),
+            ] {
+                m.add(
+                    "gmon_gpu_clock_hertz",
+                    "gauge",
+                    "Current GPU clock.",
+                    labels(&[("gpu", &index), ("uuid", uuid), ("clock", clock)]),
+                    val.val as f64 * 1e6,
+                );
+            }
+        }
+        if let Some(counters) = &device.counters {
+            if let Some(energy) = &counters.energy {
+                m.add(
+                    "gmon_gpu_energy_joules_total",
+                    "counter",
+                    "Energy consumed since the driver was loaded.",
+                    dev.clone(),
+                    energy.val as f64 / 1000.0,
+                );
+            }
+            for (kind, val) in [
+                ("corrected", &counters.ecc_corrected),
+                ("uncorrected", &counters.ecc_uncorrected),
+            ] {
+                if let Some(val) = val {
+                    m.add(
+                        "gmon_gpu_ecc_errors_total",
+                        "counter",
+                        "Volatile ECC errors.",
+                        labels(&[("gpu", &index), ("uuid", uuid), ("type", kind)]),
+                        val.val as f64,
+                    );
+                }
+            }
+        }
+    }
+
+    let mut per_container = BTreeMap::<(u32, String), u64>::new();
+    let mut per_user = BTreeMap::<(u32, String), u64>::new();
+    for proc in snapshot.processes.iter() {
+        let used = match &proc.gpu_info.used_memory {
+            Some(used) => used.val * MIB,
+            None => continue,
+        };
+        let gpu = proc.gpu_id.to_string();
+        let pid = proc.pid.to_string();
+        let user = proc.user_or_uid();
+        let container = proc
+            .container(&snapshot.containers)
+            .map(|ctr| ctr.name.clone())
+            .unwrap_or_default();
+        m.add(
+            "gmon_process_memory_used_bytes",
+            "gauge",
+            "GPU memory used by a process.",
+            labels(&[
+                ("gpu", &gpu),
+                ("pid", &pid),
+                ("command", &proc.name),
+                ("user", &user),
+                ("container", &container),
+            ]),
+            used as f64,
+        );
+        if !container.is_empty() {
+            *per_container.entry((proc.gpu_id, container)).or_default() += used;
+        }
+        *per_user.entry((proc.gpu_id, user)).or_default() += used;
+    }
+    for ((gpu, container), used) in per_container {
+        m.add(
+            "gmon_container_memory_used_bytes",
+            "gauge",
+            "GPU memory used by the processes of a container.",
+            labels(&[("gpu", &gpu.to_string()), ("container", &container)]),
+            used as f64,
+        );
+    }
+    for ((gpu, user), used) in per_user {
+        m.add(
+            "gmon_user_memory_used_bytes",
+            "gauge",
+            "GPU memory used by the processes of a user.",
+            labels(&[("gpu", &gpu.to_string()), ("user", &user)]),
+            used as f64,
+        );
+    }
+    m.finish()
+}
@@ -0,0 +1,77 @@
+use std::time::Duration;
+
+use gmon::collector::Collector;
+use gmon::procfs::ProcFs;
+use gmon::select::{free_gpus, sample_window, Criteria, FreeGpu};
+use gmon::vendors::error::BackendError;
+use gmon::vendors::traits::{GpuInfo, GpuProviderInfo};
+use serde::Serialize;
+
+use crate::cli::{CriteriaArgs, FreeArgs};
+
+#[derive(Serialize)]
+struct Report<'a> {
+    cuda_visible_devices: String,
+    gpus: &'a [FreeGpu],
+}
+
+impl CriteriaArgs {
+    pub fn criteria(&self) -> Criteria {
+        Criteria {
+            min_free_memory: (self.min_free * 1024.0).ceil() as u64,
+            max_utilization: self.max_util,
+            uid: Some(unsafe { libc::getuid() }),
+            owner: ProcFs::default().owner(std::process::id()),
+            own_claims: true,
+        }
+    }
+}
+
+/// The value for `CUDA_VISIBLE_DEVICES`: NVML indices, which follow PCI bus
+/// order like CUDA does with `CUDA_DEVICE_ORDER=PCI_BUS_ID`, or UUIDs, which
+/// are unambiguous either way.
+pub fn visible_devices(gpus: &[FreeGpu], uuids: bool) -> String {
+    gpus.iter()
+        .map(|gpu| match (&gpu.uuid, uuids) {
+            (Some(uuid), true) => uuid.clone(),
+            _ => gpu.index.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// Prints the free GPUs and reports whether enough were found.
+pub fn run<'a, P, G>(
+    collector: &mut Collector<'a, P>,
+    args: &FreeArgs,
+    interval: Duration,
+) -> Result<bool, BackendError>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    let window = Duration::from_secs_f64(args.criteria.window);
+    let samples = sample_window(collector, window, interval)?;
+    let mut gpus = free_gpus(&samples, &args.criteria.criteria());
+    let enough = match args.count {
+        Some(count) => {
+            gpus.truncate(count);
+            gpus.len() == count
+        }
+        None => !gpus.is_empty(),
+    };
+    let devices = visible_devices(&gpus, args.uuids);
+    if args.json {
+        let report = Report {
+            cuda_visible_devices: devices,
+            gpus: &gpus,
+        };
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report).expect("report is always serializable")
+        );
+    } else {
+        println!("CUDA_VISIBLE_DEVICES={}", devices);
+    }
+    Ok(enough)
+}
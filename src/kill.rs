@@ -0,0 +1,192 @@
+use std::io;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use gmon::collector::Collector;
+use gmon::process::pid_alive;
+use gmon::users::uid_by_name;
+use gmon::vendors::error::BackendError;
+use gmon::vendors::traits::{GpuInfo, GpuProviderInfo};
+use thiserror::Error;
+
+use crate::cli::{KillArgs, Signal};
+
+/// How often processes are checked during the grace period.
+const GRACE_POLL: Duration = Duration::from_millis(100);
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Backend(#[from] BackendError),
+    #[error("unknown user \"{0}\"")]
+    UnknownUser(String),
+    #[error("select processes with --gpu, --user, --container or --pid")]
+    NoSelection,
+    #[error("refusing to signal processes of other users without root:\n{0}")]
+    OtherUsers(String),
+}
+
+/// A GPU process to be signalled.
+struct Target {
+    pid: u32,
+    /// `pid` inside its container.
+    container_pid: Option<u32>,
+    uid: u32,
+    gpus: Vec<u32>,
+    command: String,
+    container: Option<String>,
+}
+
+impl Target {
+    fn describe(&self) -> String {
+        let gpus = self
+            .gpus
+            .iter()
+            .map(|gpu| gpu.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut text = format!(
+            "{} ({}, uid {}, GPU {}",
+            self.pid, self.command, self.uid, gpus
+        );
+        if let Some(container) = &self.container {
+            text += &format!(", container {}", container);
+        }
+        if let Some(inner) = self.container_pid {
+            text += &format!(", PID {} inside", inner);
+        }
+        text + ")"
+    }
+}
+
+fn send(pid: u32, signal: Signal) -> io::Result<()> {
+    // kill(0, ...) would signal gmon's own process group.
+    let pid = libc::pid_t::try_from(pid)
+        .ok()
+        .filter(|pid| *pid > 0)
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    if unsafe { libc::kill(pid, signal.0) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Signals the selected GPU processes, returning whether all of them were
+/// reached.
+pub fn run<'a, P, G>(collector: &mut Collector<'a, P>, args: &KillArgs) -> Result<bool, Error>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    if args.gpu.is_none() && args.user.is_none() && args.container.is_none() && args.pid.is_empty()
+    {
+        return Err(Error::NoSelection);
+    }
+    let uid = match &args.user {
+        Some(user) => Some(
+            user.parse::<u32>()
+                .ok()
+                .or_else(|| uid_by_name(user))
+                .ok_or_else(|| Error::UnknownUser(user.clone()))?,
+        ),
+        None => None,
+    };
+    let snapshot = collector.sample()?;
+    let mut targets: Vec<Target> = vec![];
+    for proc in snapshot.processes.iter() {
+        let container = proc.container(&snapshot.containers);
+        let selected = args
+            .gpu
+            .as_ref()
+            .is_none_or(|set| set.0.contains(&proc.gpu_id))
+            && uid.is_none_or(|uid| proc.uid == uid)
+            && (args.pid.is_empty() || args.pid.contains(&proc.pid))
+            && args.container.as_ref().is_none_or(|want| {
+                container.is_some_and(|ctr| ctr.name == *want || ctr.id.starts_with(want.as_str()))
+            });
+        if !selected {
+            continue;
+        }
+        // A process using several GPUs is listed once per GPU.
+        match targets.iter_mut().find(|target| target.pid == proc.pid) {
+            Some(target) => target.gpus.push(proc.gpu_id),
+            None => targets.push(Target {
+                pid: proc.pid,
+                container_pid: proc.container_pid(),
+                uid: proc.uid,
+                gpus: vec![proc.gpu_id],
+                command: proc.name.clone(),
+                container: container.map(|ctr| ctr.name.clone()),
+            }),
+        }
+    }
+    if targets.is_empty() {
+        eprintln!("gmon: no matching GPU processes");
+        return Ok(false);
+    }
+    let me = unsafe { libc::getuid() };
+    if me != 0 {
+        let others = targets
+            .iter()
+            .filter(|target| target.uid != me)
+            .map(|target| format!("  {}", target.describe()))
+            .collect::<Vec<_>>();
+        if !others.is_empty() {
+            return Err(Error::OtherUsers(others.join("\n")));
+        }
+    }
+
+    let verb = if args.dry_run { "would send" } else { "sent" };
+    let mut ok = true;
+    let mut signalled = vec![];
+    for target in targets.iter() {
+        if !args.dry_run {
+            match send(target.pid, args.signal) {
+                Ok(()) => {}
+                Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {
+                    println!("{} has already exited", target.pid);
+                    continue;
+                }
+                Err(e) => {
+                    eprintln!("gmon: failed to signal {}: {}", target.pid, e);
+                    ok = false;
+                    continue;
+                }
+            }
+        }
+        println!("{} {} to {}", verb, args.signal.name(), target.describe());
+        signalled.push(target);
+    }
+
+    let grace = match args.grace {
+        Some(secs) if args.signal.0 != libc::SIGKILL && !signalled.is_empty() => {
+            Duration::from_secs_f64(secs)
+        }
+        _ => return Ok(ok),
+    };
+    let kill = Signal(libc::SIGKILL);
+    if args.dry_run {
+        println!(
+            "would send {} after {:?} to any of them still running",
+            kill.name(),
+            grace
+        );
+        return Ok(ok);
+    }
+    let deadline = Instant::now() + grace;
+    while Instant::now() < deadline && signalled.iter().any(|target| pid_alive(target.pid)) {
+        sleep(GRACE_POLL);
+    }
+    for target in signalled.iter().filter(|target| pid_alive(target.pid)) {
+        match send(target.pid, kill) {
+            Ok(()) => println!("sent {} to {}", kill.name(), target.describe()),
+            Err(e) if e.raw_os_error() == Some(libc::ESRCH) => {}
+            Err(e) => {
+                eprintln!("gmon: failed to signal {}: {}", target.pid, e);
+                ok = false;
+            }
+        }
+    }
+    Ok(ok)
+}
@@ -0,0 +1,635 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+/// Where the kubelet serves the pod-resources API.
+pub const DEFAULT_SOCKET: &str = "/var/lib/kubelet/pod-resources/kubelet.sock";
+
+/// Where container runtimes keep the OCI config of a container, by ID:
+/// containerd's CRI plugin and CRI-O.
+const BUNDLE_CONFIGS: [&str; 2] = [
+    "/run/containerd/io.containerd.runtime.v2.task/k8s.io/{}/config.json",
+    "/run/containers/storage/overlay-containers/{}/userdata/config.json",
+];
+
+const METHOD: &str = "/v1.PodResourcesLister/List";
+const TIMEOUT: Duration = Duration::from_secs(5);
+/// Largest response accepted, well above what a node's pods need.
+const MAX_RESPONSE: usize = 64 << 20;
+
+#[derive(Error, Debug)]
+pub enum KubeletError {
+    #[error("{0}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("unexpected response from the kubelet: {0}")]
+    Protocol(&'static str),
+    #[error("the kubelet failed the request with gRPC status {0}: {1}")]
+    Status(u32, String),
+}
+
+/// A container and the GPU it was allocated.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct PodRef {
+    pub namespace: String,
+    pub pod: String,
+    pub container: String,
+}
+
+impl fmt::Display for PodRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}/{}", self.namespace, self.pod, self.container)
+    }
+}
+
+/// A GPU allocated to a container.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct GpuAllocation {
+    pub uuid: String,
+    #[serde(flatten)]
+    pub pod: PodRef,
+}
+
+/// Devices of one resource, e.g. `nvidia.com/gpu`, allocated to a container.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContainerDevices {
+    pub resource_name: String,
+    pub device_ids: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ContainerResources {
+    pub name: String,
+    pub devices: Vec<ContainerDevices>,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PodResources {
+    pub name: String,
+    pub namespace: String,
+    pub containers: Vec<ContainerResources>,
+}
+
+/// Reads the protobuf fields of `buf` as `(number, value)`, skipping
+/// scalar fields other than length-delimited ones.
+fn fields(mut buf: &[u8]) -> Result<Vec<(u64, &[u8])>, KubeletError> {
+    fn varint(buf: &mut &[u8]) -> Result<u64, KubeletError> {
+        let mut val = 0u64;
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = buf
+                .split_first()
+                .ok_or(KubeletError::Protocol("truncated varint"))?;
+            *buf = rest;
+            val |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(val);
+            }
+        }
+        Err(KubeletError::Protocol("overlong varint"))
+    }
+    let mut out = vec![];
+    while !buf.is_empty() {
+        let key = varint(&mut buf)?;
+        let skip = match key & 7 {
+            0 => {
+                varint(&mut buf)?;
+                0
+            }
+            1 => 8,
+            2 => {
+                let len = usize::try_from(varint(&mut buf)?)
+                    .map_err(|_| KubeletError::Protocol("field too long"))?;
+                let val = buf
+                    .get(..len)
+                    .ok_or(KubeletError::Protocol("truncated field"))?;
+                out.push((key >> 3, val));
+                len
+            }
+            5 => 4,
+            _ => return Err(KubeletError::Protocol("unknown wire type")),
+        };
+        buf = buf
+            .get(skip..)
+            .ok_or(KubeletError::Protocol("truncated field"))?;
+    }
+    Ok(out)
+}
+
+fn string(val: &[u8]) -> String {
+    String::from_utf8_lossy(val).into_owned()
+}
+
+impl ContainerDevices {
+    fn decode(buf: &[u8]) -> Result<Self, KubeletError> {
+        let mut devices = ContainerDevices::default();
+        for (number, val) in fields(buf)? {
+            match number {
+                1 => devices.resource_name = string(val),
+                2 => devices.device_ids.push(string(val)),
+                _ => {}
+            }
+        }
+        Ok(devices)
+    }
+}
+
+impl ContainerResources {
+    fn decode(buf: &[u8]) -> Result<Self, KubeletError> {
+        let mut container = ContainerResources::default();
+        for (number, val) in fields(buf)? {
+            match number {
+                1 => container.name = string(val),
+                2 => container.devices.push(ContainerDevices::decode(val)?),
+                _ => {}
+            }
+        }
+        Ok(container)
+    }
+}
+
+impl PodResources {
+    fn decode(buf: &[u8]) -> Result<Self, KubeletError> {
+        let mut pod = PodResources::default();
+        for (number, val) in fields(buf)? {
+            match number {
+                1 => pod.name = string(val),
+                2 => pod.namespace = string(val),
+                3 => pod.containers.push(ContainerResources::decode(val)?),
+                _ => {}
+            }
+        }
+        Ok(pod)
+    }
+}
+
+/// Decodes a `ListPodResourcesResponse`.
+fn decode_list(buf: &[u8]) -> Result<Vec<PodResources>, KubeletError> {
+    fields(buf)?
+        .into_iter()
+        .filter(|(number, _)| *number == 1)
+        .map(|(_, val)| PodResources::decode(val))
+        .collect()
+}
+
+/// The GPUs allocated to containers in `pods`. Devices count as GPUs if
+/// their ID is a GPU or MIG UUID; shared GPUs, listed as `UUID::N`, are
+/// allocated to each container sharing them.
+///
+/// ```
+/// use gmon::kubelet::{gpu_allocations, ContainerDevices, ContainerResources, PodResources};
+///
+/// let pods = vec![PodResources {
+///     name: String::from("train-0"),
+///     namespace: String::from("ml"),
+///     containers: vec![ContainerResources {
+///         name: String::from("pytorch"),
+///         devices: vec![ContainerDevices {
+///             resource_name: String::from("nvidia.com/gpu"),
+///             device_ids: vec![String::from("GPU-5e1f::1")],
+///         }],
+///     }],
+/// }];
+/// let allocations = gpu_allocations(&pods);
+/// assert_eq!(allocations[0].uuid, "GPU-5e1f");
+/// assert_eq!(allocations[0].pod.to_string(), "ml/train-0/pytorch");
+/// ```
+pub fn gpu_allocations(pods: &[PodResources]) -> Vec<GpuAllocation> {
+    let mut allocations = vec![];
+    for pod in pods {
+        for container in pod.containers.iter() {
+            let ids = container
+                .devices
+                .iter()
+                .flat_map(|devices| devices.device_ids.iter());
+            for id in ids {
+                let uuid = id.split("::").next().unwrap_or(id);
+                if !uuid.starts_with("GPU-") && !uuid.starts_with("MIG-") {
+                    continue;
+                }
+                allocations.push(GpuAllocation {
+                    uuid: uuid.to_string(),
+                    pod: PodRef {
+                        namespace: pod.namespace.clone(),
+                        pod: pod.name.clone(),
+                        container: container.name.clone(),
+                    },
+                });
+            }
+        }
+    }
+    allocations
+}
+
+/// The containers each GPU, by UUID, was allocated to.
+pub fn by_uuid(allocations: &[GpuAllocation]) -> HashMap<&str, Vec<&PodRef>> {
+    let mut map: HashMap<&str, Vec<&PodRef>> = HashMap::new();
+    for allocation in allocations {
+        map.entry(allocation.uuid.as_str())
+            .or_default()
+            .push(&allocation.pod);
+    }
+    map
+}
+
+#[derive(Deserialize)]
+struct Bundle {
+    #[serde(default)]
+    annotations: HashMap<String, String>,
+}
+
+/// The pod UID, if recorded, and the container a runtime wrote into the
+/// annotations of an OCI `config.json`.
+fn bundle_pod(config: &str) -> Option<(Option<String>, PodRef)> {
+    let bundle: Bundle = serde_json::from_str(config).ok()?;
+    // containerd's names first, then CRI-O's.
+    let get = |keys: [&str; 2]| {
+        keys.iter()
+            .find_map(|key| bundle.annotations.get(*key))
+            .cloned()
+    };
+    let uid = get(["io.kubernetes.cri.sandbox-uid", "io.kubernetes.pod.uid"]);
+    let pod = PodRef {
+        namespace: get([
+            "io.kubernetes.cri.sandbox-namespace",
+            "io.kubernetes.pod.namespace",
+        ])?,
+        pod: get(["io.kubernetes.cri.sandbox-name", "io.kubernetes.pod.name"])?,
+        container: get([
+            "io.kubernetes.cri.container-name",
+            "io.kubernetes.container.name",
+        ])?,
+    };
+    Some((uid, pod))
+}
+
+/// The pod and container of the container `id` in the pod `uid`, as both
+/// come from a process's control group, read from the runtime's state.
+///
+/// Works with containerd and CRI-O, and usually needs root.
+pub fn container_pod(uid: &str, id: &str) -> Option<PodRef> {
+    if !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    BUNDLE_CONFIGS.iter().find_map(|path| {
+        let config = fs::read_to_string(path.replace("{}", id)).ok()?;
+        match bundle_pod(&config)? {
+            (Some(pod_uid), _) if pod_uid != uid => None,
+            (_, pod) => Some(pod),
+        }
+    })
+}
+
+// HTTP/2 frame types and flags used by the client.
+const DATA: u8 = 0x0;
+const HEADERS: u8 = 0x1;
+const RST_STREAM: u8 = 0x3;
+const SETTINGS: u8 = 0x4;
+const PING: u8 = 0x6;
+const GOAWAY: u8 = 0x7;
+const WINDOW_UPDATE: u8 = 0x8;
+const CONTINUATION: u8 = 0x9;
+const END_STREAM: u8 = 0x1;
+const ACK: u8 = 0x1;
+const END_HEADERS: u8 = 0x4;
+const PADDED: u8 = 0x8;
+const PRIORITY: u8 = 0x20;
+
+fn frame(kind: u8, flags: u8, stream: u32, payload: &[u8]) -> Vec<u8> {
+    let len = payload.len() as u32;
+    let mut buf = vec![(len >> 16) as u8, (len >> 8) as u8, len as u8, kind, flags];
+    buf.extend_from_slice(&stream.to_be_bytes());
+    buf.extend_from_slice(payload);
+    buf
+}
+
+/// The payload of a DATA or HEADERS frame without its padding.
+fn unpadded(payload: &[u8], flags: u8) -> Result<&[u8], KubeletError> {
+    if flags & PADDED == 0 {
+        return Ok(payload);
+    }
+    let pad = usize::from(*payload.first().unwrap_or(&0));
+    payload
+        .get(1..payload.len().saturating_sub(pad))
+        .ok_or(KubeletError::Protocol("bad padding"))
+}
+
+fn request_headers() -> Vec<u8> {
+    let headers = [
+        (":method", "POST"),
+        (":scheme", "http"),
+        (":path", METHOD),
+        (":authority", "localhost"),
+        ("content-type", "application/grpc"),
+        ("te", "trailers"),
+    ]
+    .iter()
+    .map(|(name, val)| (name.as_bytes().to_vec(), val.as_bytes().to_vec()))
+    .collect();
+    hpack::Encoder::new().encode(&headers)
+}
+
+/// What the response headers and trailers say about the call.
+#[derive(Debug, Default)]
+struct CallStatus {
+    http: Option<String>,
+    grpc: Option<String>,
+    message: String,
+}
+
+impl CallStatus {
+    fn update(&mut self, headers: Vec<(Vec<u8>, Vec<u8>)>) {
+        for (name, val) in headers {
+            let val = string(&val);
+            match name.as_slice() {
+                b":status" => self.http = Some(val),
+                b"grpc-status" => self.grpc = Some(val),
+                b"grpc-message" => self.message = val,
+                _ => {}
+            }
+        }
+    }
+
+    /// Fails unless the call ended with HTTP 200 and gRPC status 0.
+    fn check(&self) -> Result<(), KubeletError> {
+        if self.http.as_deref() != Some("200") {
+            return Err(KubeletError::Protocol("HTTP status other than 200"));
+        }
+        match self.grpc.as_deref().map(str::parse::<u32>) {
+            Some(Ok(0)) => Ok(()),
+            Some(Ok(code)) => Err(KubeletError::Status(code, self.message.clone())),
+            Some(Err(_)) => Err(KubeletError::Protocol("bad grpc-status")),
+            None => Err(KubeletError::Protocol("no grpc-status")),
+        }
+    }
+}
+
+/// A client of the kubelet pod-resources API, speaking just enough gRPC
+/// over HTTP/2 for its `List` call.
+#[derive(Debug, Clone)]
+pub struct PodResourcesClient {
+    socket: PathBuf,
+}
+
+impl PodResourcesClient {
+    pub fn new(socket: impl AsRef<Path>) -> Self {
+        PodResourcesClient {
+            socket: socket.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn socket(&self) -> &Path {
+        &self.socket
+    }
+
+    /// The pods on this node and the devices allocated to their containers.
+    pub fn list(&self) -> Result<Vec<PodResources>, KubeletError> {
+        let io_err = |e| KubeletError::Io(self.socket.clone(), e);
+        let mut stream = UnixStream::connect(&self.socket).map_err(io_err)?;
+        stream.set_read_timeout(Some(TIMEOUT)).map_err(io_err)?;
+        stream.set_write_timeout(Some(TIMEOUT)).map_err(io_err)?;
+
+        let mut out = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n".to_vec();
+        out.extend(frame(SETTINGS, 0, 0, &[]));
+        out.extend(frame(HEADERS, END_HEADERS, 1, &request_headers()));
+        // An empty ListPodResourcesRequest: uncompressed, zero length.
+        out.extend(frame(DATA, END_STREAM, 1, &[0; 5]));
+        stream.write_all(&out).map_err(io_err)?;
+
+        let mut body = vec![];
+        let mut status = CallStatus::default();
+        // Header blocks share one compression context per connection.
+        let mut decoder = hpack::Decoder::new();
+        let mut block = vec![];
+        let mut block_ends_stream = false;
+        loop {
+            let mut head = [0u8; 9];
+            stream.read_exact(&mut head).map_err(io_err)?;
+            let len = usize::from(head[0]) << 16 | usize::from(head[1]) << 8 | usize::from(head[2]);
+            let (kind, flags) = (head[3], head[4]);
+            let id = u32::from_be_bytes([head[5], head[6], head[7], head[8]]) & 0x7fff_ffff;
+            let mut payload = vec![0u8; len];
+            stream.read_exact(&mut payload).map_err(io_err)?;
+            let mut ended = false;
+            match kind {
+                SETTINGS if flags & ACK == 0 => {
+                    stream
+                        .write_all(&frame(SETTINGS, ACK, 0, &[]))
+                        .map_err(io_err)?;
+                }
+                PING if flags & ACK == 0 => {
+                    stream
+                        .write_all(&frame(PING, ACK, 0, &payload))
+                        .map_err(io_err)?;
+                }
+                DATA if id == 1 => {
+                    body.extend_from_slice(unpadded(&payload, flags)?);
+                    if body.len() > MAX_RESPONSE {
+                        return Err(KubeletError::Protocol("response too large"));
+                    }
+                    if len > 0 {
+                        // Keep the flow-control windows open for the rest.
+                        let inc = (len as u32).to_be_bytes();
+                        let mut out = frame(WINDOW_UPDATE, 0, 0, &inc);
+                        out.extend(frame(WINDOW_UPDATE, 0, 1, &inc));
+                        stream.write_all(&out).map_err(io_err)?;
+                    }
+                    ended = flags & END_STREAM != 0;
+                }
+                HEADERS | CONTINUATION => {
+                    let mut fragment = match kind {
+                        HEADERS => {
+                            block.clear();
+                            block_ends_stream = id == 1 && flags & END_STREAM != 0;
+                            unpadded(&payload, flags)?
+                        }
+                        _ => &payload[..],
+                    };
+                    if kind == HEADERS && flags & PRIORITY != 0 {
+                        fragment = fragment
+                            .get(5..)
+                            .ok_or(KubeletError::Protocol("bad priority"))?;
+                    }
+                    block.extend_from_slice(fragment);
+                    if block.len() > MAX_RESPONSE {
+                        return Err(KubeletError::Protocol("headers too large"));
+                    }
+                    if flags & END_HEADERS != 0 {
+                        let headers = decoder
+                            .decode(&block)
+                            .map_err(|_| KubeletError::Protocol("bad header block"))?;
+                        if id == 1 {
+                            status.update(headers);
+                            ended = block_ends_stream;
+                        }
+                    }
+                }
+                RST_STREAM if id == 1 => return Err(KubeletError::Protocol("stream reset")),
+                GOAWAY => return Err(KubeletError::Protocol("connection closed")),
+                _ => {}
+            }
+            if ended {
+                break;
+            }
+        }
+        // Failed calls usually carry no message, only the status.
+        status.check()?;
+
+        // One length-prefixed message, uncompressed.
+        let (prefix, message) = match body.len() {
+            5.. => body.split_at(5),
+            _ => return Err(KubeletError::Protocol("no message")),
+        };
+        if prefix[0] != 0 {
+            return Err(KubeletError::Protocol("compressed message"));
+        }
+        let len = u32::from_be_bytes([prefix[1], prefix[2], prefix[3], prefix[4]]) as usize;
+        let message = message
+            .get(..len)
+            .ok_or(KubeletError::Protocol("truncated message"))?;
+        decode_list(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::io::{self, Write};
+    use std::os::unix::net::UnixListener;
+    use std::path::PathBuf;
+    use std::thread::{self, JoinHandle};
+
+    use super::{bundle_pod, gpu_allocations, KubeletError, PodResources, PodResourcesClient};
+    use crate::collector::Collector;
+    use crate::procfs::ProcFs;
+    use crate::vendors::fake::{FakeGpu, FakeGpuProvider};
+    use crate::vendors::types::ProcessInfo;
+
+    /// A socket that answers one call with `recorded`, the bytes a server
+    /// sent for it.
+    fn replay(name: &str, recorded: &'static [u8]) -> (PathBuf, JoinHandle<()>) {
+        let socket =
+            std::env::temp_dir().join(format!("gmon-kubelet-{}-{}", name, std::process::id()));
+        fs::remove_file(&socket).ok();
+        let listener = UnixListener::bind(&socket).unwrap();
+        let server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(recorded).unwrap();
+            io::copy(&mut stream, &mut io::sink()).ok();
+        });
+        (socket, server)
+    }
+
+    fn list(name: &str, recorded: &'static [u8]) -> Result<Vec<PodResources>, KubeletError> {
+        let (socket, server) = replay(name, recorded);
+        let res = PodResourcesClient::new(&socket).list();
+        server.join().unwrap();
+        fs::remove_file(&socket).ok();
+        res
+    }
+
+    #[test]
+    fn lists_pod_resources() {
+        let pods = list("list", include_bytes!("../testdata/kubelet-list.h2")).unwrap();
+        let names = pods.iter().map(|pod| pod.name.as_str()).collect::<Vec<_>>();
+        assert_eq!(names, ["train-0", "infer", "shared"]);
+        let allocations = gpu_allocations(&pods)
+            .into_iter()
+            .map(|a| format!("{} {}", a.pod, a.uuid))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            allocations,
+            [
+                "ml/train-0/pytorch GPU-00000000-0000-0000-0000-000000000001",
+                "ml/train-0/pytorch GPU-00000000-0000-0000-0000-000000000003",
+                "ml/infer/server MIG-00000000-0000-0000-0000-0000000000aa",
+                "default/shared/a GPU-00000000-0000-0000-0000-000000000000",
+                "default/shared/b GPU-00000000-0000-0000-0000-000000000000",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_grpc_errors() {
+        let recorded = include_bytes!("../testdata/kubelet-unimplemented.h2");
+        match list("unimplemented", recorded) {
+            Err(KubeletError::Status(12, message)) => {
+                assert_eq!(
+                    message,
+                    "unknown method List for service v1.PodResourcesLister"
+                )
+            }
+            res => panic!("expected status 12, got {:?}", res),
+        }
+    }
+
+    #[test]
+    fn reads_pods_from_runtime_annotations() {
+        let containerd = r#"{"ociVersion": "1.1.0", "annotations": {
+            "io.kubernetes.cri.container-type": "container",
+            "io.kubernetes.cri.container-name": "pytorch",
+            "io.kubernetes.cri.sandbox-name": "train-0",
+            "io.kubernetes.cri.sandbox-namespace": "ml",
+            "io.kubernetes.cri.sandbox-uid": "0f2c6a1e-1b7f-4d8e-9c3a-2a9d6b3c4e5f"
+        }}"#;
+        let (uid, pod) = bundle_pod(containerd).unwrap();
+        assert_eq!(uid.as_deref(), Some("0f2c6a1e-1b7f-4d8e-9c3a-2a9d6b3c4e5f"));
+        assert_eq!(pod.to_string(), "ml/train-0/pytorch");
+
+        let crio = r#"{"annotations": {
+            "io.kubernetes.container.name": "server",
+            "io.kubernetes.pod.name": "infer",
+            "io.kubernetes.pod.namespace": "ml",
+            "io.kubernetes.pod.uid": "5e1f0a2b-0000-4000-8000-000000000001"
+        }}"#;
+        assert_eq!(bundle_pod(crio).unwrap().1.to_string(), "ml/infer/server");
+
+        // Not a Kubernetes container.
+        assert!(bundle_pod(r#"{"ociVersion": "1.1.0"}"#).is_none());
+    }
+
+    #[test]
+    fn attributes_processes_on_mig_devices() {
+        let pid = std::process::id();
+        let root = std::env::temp_dir().join(format!("gmon-kubelet-proc-{}", pid));
+        fs::create_dir_all(root.join(pid.to_string())).unwrap();
+        // A container the runtime state does not know, as without root.
+        fs::write(
+            root.join(pid.to_string()).join("cgroup"),
+            format!(
+                "0::/kubepods.slice/kubepods-pod5e1f0a2b_0000_4000_8000_000000000001.slice/cri-containerd-{}.scope\n",
+                "ab".repeat(32)
+            ),
+        )
+        .unwrap();
+        let mut gpu = FakeGpu::idle(2);
+        gpu.mig_uuids = vec![String::from("MIG-00000000-0000-0000-0000-0000000000aa")];
+        gpu.processes = vec![ProcessInfo {
+            pid,
+            used_memory: None,
+        }];
+        let provider = FakeGpuProvider::new(vec![gpu]);
+
+        let (socket, server) = replay("mig", include_bytes!("../testdata/kubelet-list.h2"));
+        let snapshot = Collector::new(&provider, false)
+            .with_procfs(ProcFs::new(&root))
+            .with_kubelet(PodResourcesClient::new(&socket))
+            .sample()
+            .unwrap();
+        server.join().unwrap();
+        fs::remove_file(&socket).ok();
+        fs::remove_dir_all(&root).ok();
+
+        let pod = snapshot.processes[0]
+            .pod
+            .as_ref()
+            .map(|pod| pod.to_string());
+        assert_eq!(pod.as_deref(), Some("ml/infer/server"));
+    }
+}
@@ -0,0 +1,40 @@
+//! Query GPU status the way the `gmon` cli does.
+//!
+//! A [`Snapshot`] is collected once from a GPU provider and owns everything
+//! it reports: devices, the processes running on them and the containers
+//! those processes belong to.
+//!
+//! ```
+//! use gmon::vendors::fake::FakeGpuProvider;
+//! use gmon::Snapshot;
+//!
+//! let provider = FakeGpuProvider::with_idle_devices(2);
+//! let snapshot = Snapshot::collect(&provider, false)?;
+//! for device in &snapshot.devices {
+//!     println!("{} {:?}", device.index, device.memory);
+//! }
+//! # Ok::<(), gmon::BackendError>(())
+//! ```
+//!
+//! On a host with NVIDIA GPUs use [`vendors::nvidia::NvGpuProvider`]
+//! instead.
+
+pub mod cgroup;
+pub mod claim;
+pub mod collector;
+pub mod docker;
+pub mod export;
+pub mod kubelet;
+pub mod process;
+pub mod procfs;
+pub mod select;
+pub mod serve;
+pub mod slurm;
+pub mod snapshot;
+pub mod spool;
+pub mod users;
+mod utils;
+pub mod vendors;
+
+pub use snapshot::Snapshot;
+pub use vendors::error::BackendError;
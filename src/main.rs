@@ -1,4 +1,377 @@
+mod claim_cmd;
 mod cli;
-mod vendors;
+mod config;
+mod display;
+mod doctor;
+mod free;
+mod kill;
+mod queue;
+mod template;
+mod tui;
+mod wait;
+mod watch;
 
-fn main() {}
+use clap::Parser;
+
+use std::cell::Cell;
+use std::net::TcpListener;
+use std::time::Duration;
+
+use cli::{Backend, Command, ConfigCommand, Format, QueueCommand};
+use gmon::claim::ClaimStore;
+use gmon::collector::Collector;
+use gmon::export::csv::{CsvOptions, Query};
+use gmon::export::{gpustat, json};
+use gmon::kubelet::PodResourcesClient;
+use gmon::procfs::ProcFs;
+use gmon::serve;
+use gmon::slurm::Slurm;
+use gmon::snapshot::Snapshot;
+use gmon::spool::Spool;
+use gmon::vendors::fake::FakeGpuProvider;
+use gmon::vendors::nvidia::NvGpuProvider;
+use gmon::vendors::traits::{GpuInfo, GpuProviderInfo};
+use template::{GpuTemplate, ProcessTemplate};
+
+/// Devices reported by `--backend fake`.
+const FAKE_GPUS: u32 = 2;
+
+fn exit_with(code: i32, msg: impl std::fmt::Display) -> ! {
+    eprintln!("gmon: {}", msg);
+    std::process::exit(code)
+}
+
+/// How samples are printed, resolved from flags and the config file.
+struct Output {
+    query: Option<Query>,
+    csv_opts: CsvOptions,
+    gpustat: bool,
+    json: bool,
+    json_lines: bool,
+    gpu_template: Option<GpuTemplate>,
+    process_template: Option<ProcessTemplate>,
+}
+
+impl Output {
+    fn new(args: &cli::Args, config: &config::Config) -> Self {
+        let query = match (&args.query_gpu, &args.query_compute_apps) {
+            (Some(fields), _) => Some(Query::gpu(fields)),
+            (None, Some(fields)) => Some(Query::compute_apps(fields)),
+            (None, None) => None,
+        };
+        let query = query.transpose().unwrap_or_else(|e| exit_with(2, e));
+        let gpustat = matches!(args.format, Some(Format::GpustatJson));
+        let machine = query.is_some() || args.json || args.json_lines;
+        if gpustat && machine {
+            exit_with(
+                2,
+                "--format gpustat-json cannot be combined with queries or --json",
+            );
+        }
+        if matches!(args.format, Some(Format::Template(_))) && machine {
+            exit_with(
+                2,
+                "--format templates cannot be combined with queries or --json",
+            );
+        }
+        let csv_opts = match &args.format {
+            Some(Format::Csv(_)) if query.is_none() => exit_with(
+                2,
+                "--format csv requires --query-gpu or --query-compute-apps",
+            ),
+            Some(Format::Csv(opts)) => opts.clone(),
+            _ => CsvOptions::default(),
+        };
+        let gpu_template = config.display.gpu_format.as_deref().map(|text| {
+            text.parse::<GpuTemplate>()
+                .unwrap_or_else(|e| exit_with(2, format!("display.gpu_format: {}", e)))
+        });
+        let process_template = config.display.process_format.as_deref().map(|text| {
+            text.parse::<ProcessTemplate>()
+                .unwrap_or_else(|e| exit_with(2, format!("display.process_format: {}", e)))
+        });
+        let gpu_template = match (gpu_template, &process_template) {
+            (None, Some(_)) => Some(
+                template::DEFAULT_GPU_FORMAT
+                    .parse::<GpuTemplate>()
+                    .expect("default template is valid"),
+            ),
+            (gpu_template, _) => gpu_template,
+        };
+        Output {
+            query,
+            csv_opts,
+            gpustat,
+            json: args.json,
+            json_lines: args.json_lines,
+            gpu_template,
+            process_template,
+        }
+    }
+
+    fn body(&self, snapshot: &Snapshot) -> String {
+        match &self.gpu_template {
+            Some(gpu) => template::render_templates(snapshot, gpu, self.process_template.as_ref()),
+            None => display::render_gpu_info_basic(snapshot),
+        }
+    }
+}
+
+fn main() {
+    let args = cli::Args::parse();
+    // Diagnosing a broken setup must not depend on a valid config.
+    let lenient = matches!(&args.command, Some(Command::Doctor | Command::Schema));
+    let mut config = match config::Config::load() {
+        Ok(config) => config,
+        Err(e) if lenient => {
+            eprintln!("gmon: ignoring config: {}", e);
+            config::Config::default()
+        }
+        Err(e) => exit_with(1, e),
+    };
+    config.apply_args(&args);
+    match config.display_settings() {
+        Ok(settings) => display::configure(settings),
+        Err(e) if lenient => eprintln!("gmon: ignoring config: {}", e),
+        Err(e) => exit_with(1, e),
+    }
+    match &args.command {
+        Some(Command::Doctor) => {
+            let kubelet = config
+                .kubernetes
+                .enabled
+                .then(|| PodResourcesClient::new(&config.kubernetes.socket));
+            doctor::run(&config.nvml.lib_paths, kubelet.as_ref());
+            return;
+        }
+        Some(Command::Schema) => {
+            println!("{}", json::schema());
+            return;
+        }
+        Some(Command::Config {
+            command: ConfigCommand::Show,
+        }) => {
+            for source in config.sources.iter() {
+                println!("# read from {}", source.display());
+            }
+            print!(
+                "{}",
+                toml::to_string(&config).expect("config is always serializable")
+            );
+            return;
+        }
+        Some(Command::Queue { command }) => {
+            // Only the worker needs GPUs.
+            let spool = Spool::new(&config.queue.dir);
+            let res = match command {
+                QueueCommand::Submit(submit) => queue::submit(&spool, submit),
+                QueueCommand::Ls { json } => queue::list(&spool, *json),
+                QueueCommand::Cancel { ids } => queue::cancel(&spool, ids),
+                QueueCommand::Run { .. } => Ok(()),
+            };
+            if let Err(e) = res {
+                exit_with(1, e);
+            }
+            if !matches!(command, QueueCommand::Run { .. }) {
+                return;
+            }
+        }
+        _ => {}
+    }
+    let output = Output::new(&args, &config);
+    match config.backend {
+        Backend::Nvidia => {
+            let provider = NvGpuProvider::new_with_lib(&config.nvml.lib_paths)
+                .unwrap_or_else(|e| exit_with(1, format!("{} (run `gmon doctor` for details)", e)));
+            run(&provider, &args, &config, &output);
+        }
+        Backend::Fake => {
+            let provider = FakeGpuProvider::with_idle_devices(FAKE_GPUS);
+            run(&provider, &args, &config, &output);
+        }
+    }
+}
+
+/// The refresh interval, checked only where it is used.
+fn checked_interval(config: &config::Config) -> Duration {
+    if !config.interval.is_finite() || config.interval <= 0.0 {
+        exit_with(2, "interval must be a positive number of seconds");
+    }
+    Duration::from_secs_f64(config.interval)
+}
+
+fn check_seconds(name: &str, secs: f64) {
+    if !secs.is_finite() || secs < 0.0 {
+        exit_with(
+            2,
+            format!("{} must be a non-negative number of seconds", name),
+        );
+    }
+}
+
+fn run<'a, P, G>(provider: &'a P, args: &cli::Args, config: &config::Config, output: &Output)
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    let store = ClaimStore::new(&config.claims.dir);
+    // Selecting processes by container needs to know the containers.
+    let container_support = config.container_support
+        || matches!(&args.command, Some(Command::Kill(kill)) if kill.container.is_some());
+    let mut collector = Collector::new(provider, container_support)
+        .with_filter(args.device_filter())
+        .with_claims(store.clone())
+        .with_procfs(ProcFs::new(&config.proc_root))
+        .with_slurm_job(args.slurm_job);
+    if config.slurm.enabled {
+        collector = collector.with_slurm(Slurm::new(&config.slurm.squeue));
+    }
+    if config.kubernetes.enabled {
+        collector = collector.with_kubelet(PodResourcesClient::new(&config.kubernetes.socket));
+    }
+    match &args.command {
+        Some(Command::Tui) => {
+            if let Err(e) = tui::run(&mut collector, checked_interval(config)) {
+                exit_with(1, e);
+            }
+            return;
+        }
+        Some(Command::Free(free)) => {
+            check_seconds("window", free.criteria.window);
+            match free::run(&mut collector, free, checked_interval(config)) {
+                Ok(true) => return,
+                Ok(false) => std::process::exit(1),
+                Err(e) => exit_with(1, e),
+            }
+        }
+        Some(Command::Wait(wait)) => {
+            check_seconds("window", wait.criteria.window);
+            check_seconds("timeout", wait.timeout.unwrap_or(0.0));
+            if !wait.poll.is_finite() || wait.poll <= 0.0 {
+                exit_with(2, "poll must be a positive number of seconds");
+            }
+            if wait.count == 0 {
+                exit_with(2, "wait needs at least one GPU");
+            }
+            match wait::run(&mut collector, &store, wait, checked_interval(config)) {
+                Ok(Some(gpus)) => {
+                    let e = wait::exec(&wait.command, &gpus, wait.uuids);
+                    let code = if e.kind() == std::io::ErrorKind::NotFound {
+                        127
+                    } else {
+                        126
+                    };
+                    exit_with(code, format!("{}: {}", wait.command[0], e));
+                }
+                Ok(None) => exit_with(
+                    124,
+                    format!("timed out waiting for {} free GPUs", wait.count),
+                ),
+                Err(e) => exit_with(1, e),
+            }
+        }
+        Some(Command::Claim(claim)) => {
+            check_seconds("window", claim.criteria.window);
+            if let Err(e) =
+                claim_cmd::claim(&mut collector, &store, claim, checked_interval(config))
+            {
+                exit_with(1, e);
+            }
+            return;
+        }
+        Some(Command::Release(release)) => {
+            if let Err(e) = claim_cmd::release(&mut collector, &store, release) {
+                exit_with(1, e);
+            }
+            return;
+        }
+        Some(Command::Kill(kill)) => {
+            check_seconds("grace", kill.grace.unwrap_or(0.0));
+            match kill::run(&mut collector, kill) {
+                Ok(true) => return,
+                Ok(false) => std::process::exit(1),
+                Err(e) => exit_with(1, e),
+            }
+        }
+        Some(Command::Queue {
+            command: QueueCommand::Run { poll },
+        }) => {
+            if !poll.is_finite() || *poll <= 0.0 {
+                exit_with(2, "poll must be a positive number of seconds");
+            }
+            let spool = Spool::new(&config.queue.dir);
+            let poll = Duration::from_secs_f64(*poll);
+            if let Err(e) = queue::run(&mut collector, &spool, &store, poll) {
+                exit_with(1, e);
+            }
+            return;
+        }
+        Some(Command::Serve { prometheus, listen }) => {
+            if !prometheus {
+                exit_with(2, "serve needs an exporter, e.g. --prometheus");
+            }
+            let listener = match TcpListener::bind(listen) {
+                Ok(listener) => listener,
+                Err(e) => exit_with(1, e),
+            };
+            if let Ok(addr) = listener.local_addr() {
+                eprintln!("gmon: serving metrics on http://{}/metrics", addr);
+            }
+            if let Err(e) = serve::run(&listener, &mut collector) {
+                exit_with(1, e);
+            }
+            return;
+        }
+        _ => {}
+    }
+    if args.interval.is_some() {
+        let interval = checked_interval(config);
+        let res = if let Some(query) = &output.query {
+            // Like `nvidia-smi -l`, name the columns once.
+            let first = Cell::new(true);
+            watch::stream(&mut collector, interval, |snapshot| {
+                let opts = CsvOptions {
+                    header: output.csv_opts.header && first.replace(false),
+                    ..output.csv_opts.clone()
+                };
+                query.render(snapshot, &opts).trim_end().to_string()
+            })
+        } else if output.gpustat {
+            watch::stream(&mut collector, interval, |snapshot| {
+                gpustat::to_line(snapshot).expect("snapshot is always serializable")
+            })
+        } else if output.json_lines {
+            watch::stream(&mut collector, interval, |snapshot| {
+                json::to_line(snapshot).expect("snapshot is always serializable")
+            })
+        } else {
+            watch::run(&mut collector, interval, |snapshot| output.body(snapshot))
+        };
+        if let Err(e) = res {
+            exit_with(1, e);
+        }
+        return;
+    }
+    let snapshot = collector.sample().unwrap_or_else(|e| exit_with(1, e));
+    if let Some(query) = &output.query {
+        print!("{}", query.render(&snapshot, &output.csv_opts));
+    } else if output.gpustat {
+        println!(
+            "{}",
+            gpustat::to_string(&snapshot).expect("snapshot is always serializable")
+        );
+    } else if output.json {
+        println!(
+            "{}",
+            json::to_string(&snapshot).expect("snapshot is always serializable")
+        );
+    } else if output.json_lines {
+        println!(
+            "{}",
+            json::to_line(&snapshot).expect("snapshot is always serializable")
+        );
+    } else {
+        display::print_header(&snapshot);
+        print!("{}", output.body(&snapshot));
+    }
+}
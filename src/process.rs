@@ -1,116 +1,309 @@
+use crate::cgroup::Owner;
 use crate::docker::{container::Container, Docker};
-use crate::nv_gpu;
-use std::collections::HashMap;
-use sysinfo::{Pid, PidExt, Process, ProcessExt, System, SystemExt};
+use crate::kubelet::PodRef;
+use crate::slurm::SlurmJob;
+use crate::snapshot::Device;
+use crate::vendors::types::ProcessInfo;
+use chrono::{DateTime, Local, TimeZone};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use sysinfo::{Pid, PidExt, ProcessExt, System, SystemExt};
 
-pub struct GProcess<'a> {
-    pub gpu_id: u8,
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct GProcess {
+    pub gpu_id: u32,
     pub pid: u32,
+    /// Owner of the process or, for a root process started by someone else
+    /// (e.g. through sudo), of its nearest non-root ancestor outside any
+    /// container.
     pub uid: u32,
     pub gid: u32,
+    /// Name of `uid`, if it has one.
+    pub user: Option<String>,
+    /// Name of `gid`, if it has one.
+    pub group: Option<String>,
     pub ctr_pid: Option<u32>,
-    pub gpu_info: &'a nv_gpu::ProcessInfo,
+    /// PIDs of the process in its nested PID namespaces, starting with `pid`
+    /// on the host; empty if unknown.
+    #[serde(default)]
+    pub ns_pids: Vec<u32>,
+    /// Service, session, job, pod or container the process belongs to, after
+    /// its control group.
+    pub owner: Option<Owner>,
+    /// The Slurm job the process runs in, if Slurm lookups are on.
+    pub slurm_job: Option<SlurmJob>,
+    /// The Kubernetes container the process runs in, if it is in a pod and
+    /// either its container runtime names the container or the kubelet
+    /// allocated its GPU to a single container.
+    pub pod: Option<PodRef>,
+    /// Executable name, empty if the process is gone.
+    pub name: String,
+    /// Command line, empty if the process is gone or hides it.
+    #[serde(default)]
+    pub cmdline: Vec<String>,
+    pub start_time: Option<DateTime<Local>>,
+    /// Seconds since the process started.
+    pub elapsed: Option<u64>,
+    /// CPU time used, in percent of one core, since the previous sample of a
+    /// [`Collector`](crate::collector::Collector) or, on the first, over the
+    /// lifetime of the process.
+    pub cpu_percent: Option<f32>,
+    /// Resident host memory, in bytes.
+    pub rss: Option<u64>,
+    /// The topmost ancestor of the same user below its session's leader,
+    /// usually the shell: the launcher, such as torchrun or mpirun, or the
+    /// command typed that started the process; the process itself if there
+    /// is none.
+    #[serde(default)]
+    pub leader: u32,
+    /// Executable name of `leader`.
+    #[serde(default)]
+    pub leader_name: String,
+    pub gpu_info: ProcessInfo,
 }
 
-impl<'a> GProcess<'a> {
-    pub fn container(&self, pd: &'a ProcessData) -> Option<&'a Container> {
-        if pd.ctr_map.is_some() && self.ctr_pid.is_some() {
-            let map = &pd.ctr_map.as_ref().unwrap();
-            map.get(&self.ctr_pid.unwrap())
-        } else {
-            None
+impl GProcess {
+    pub fn container<'a>(&self, containers: &'a [Container]) -> Option<&'a Container> {
+        match self.ctr_pid {
+            Some(ctr_pid) => containers.iter().find(|ctr| ctr.state.pid == ctr_pid),
+            None => None,
         }
     }
-    pub fn process_info(&self, pd: &'a ProcessData) -> &'a Process {
-        let pid = Pid::from(i32::try_from(self.pid).unwrap());
-        pd.sys.process(pid).unwrap()
+    pub fn gpu_usage(&self) -> &ProcessInfo {
+        &self.gpu_info
     }
-    pub fn gpu_usage(&self) -> &'a nv_gpu::ProcessInfo {
-        self.gpu_info
+
+    /// The PID as seen inside its container, if it runs in another PID
+    /// namespace.
+    pub fn container_pid(&self) -> Option<u32> {
+        match self.ns_pids.as_slice() {
+            [_, .., inner] => Some(*inner),
+            _ => None,
+        }
+    }
+
+    /// The user name, or the uid if it has none.
+    pub fn user_or_uid(&self) -> String {
+        self.user.clone().unwrap_or_else(|| self.uid.to_string())
+    }
+}
+
+/// GPU processes sharing a [`GProcess::leader`], such as the workers of one
+/// torchrun or mpirun launch.
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct ProcessGroup {
+    pub leader: u32,
+    /// Executable name of the leader.
+    pub name: String,
+    pub uid: u32,
+    pub user: Option<String>,
+    /// Indices of the GPUs used, ascending.
+    pub gpus: Vec<u32>,
+    /// GPU memory used by all members, in MiB.
+    pub used_memory: u64,
+    /// One entry per process and GPU, like [`Snapshot::processes`](crate::Snapshot::processes).
+    pub processes: Vec<GProcess>,
+}
+
+impl ProcessGroup {
+    /// Number of distinct processes.
+    pub fn workers(&self) -> usize {
+        self.processes
+            .iter()
+            .map(|proc| proc.pid)
+            .collect::<BTreeSet<_>>()
+            .len()
     }
 }
 
-pub struct ProcessData<'a> {
-    sys: System,
-    pub gpu_procs: Vec<GProcess<'a>>,
+/// Groups `procs` by leader, in order of first appearance.
+///
+/// ```
+/// use gmon::process::group_processes;
+/// use gmon::vendors::fake::{FakeGpu, FakeGpuProvider};
+/// use gmon::vendors::types::ProcessInfo;
+/// use gmon::Snapshot;
+///
+/// let mut gpus = (0..2).map(FakeGpu::idle).collect::<Vec<_>>();
+/// for gpu in gpus.iter_mut() {
+///     gpu.processes = vec![ProcessInfo { pid: std::process::id(), used_memory: None }];
+/// }
+/// let snapshot = Snapshot::collect(&FakeGpuProvider::new(gpus), false)?;
+/// let groups = group_processes(&snapshot.processes);
+/// assert_eq!(groups.len(), 1);
+/// assert_eq!(groups[0].gpus, vec![0, 1]);
+/// assert_eq!(groups[0].workers(), 1);
+/// # Ok::<(), gmon::BackendError>(())
+/// ```
+pub fn group_processes(procs: &[GProcess]) -> Vec<ProcessGroup> {
+    let mut groups: Vec<ProcessGroup> = vec![];
+    for proc in procs {
+        let group = match groups.iter_mut().find(|group| group.leader == proc.leader) {
+            Some(group) => group,
+            None => {
+                groups.push(ProcessGroup {
+                    leader: proc.leader,
+                    name: proc.leader_name.clone(),
+                    uid: proc.uid,
+                    user: proc.user.clone(),
+                    gpus: vec![],
+                    used_memory: 0,
+                    processes: vec![],
+                });
+                groups.last_mut().expect("group was just pushed")
+            }
+        };
+        if !group.gpus.contains(&proc.gpu_id) {
+            group.gpus.push(proc.gpu_id);
+            group.gpus.sort_unstable();
+        }
+        group.used_memory += proc.gpu_info.used_memory.as_ref().map_or(0, |mem| mem.val);
+        group.processes.push(proc.clone());
+    }
+    groups
+}
+
+pub struct ProcessData {
+    pub gpu_procs: Vec<GProcess>,
     ctr_map: Option<HashMap<u32, Container>>,
-    pub gpu_info: &'a nv_gpu::GPUInfo,
 }
 
-fn get_process(
-    sys: &System,
-    pid: u32,
-    ctr_map: &Option<HashMap<u32, Container>>,
-) -> (u32, u32, Option<u32>) {
-    let mut uid = 0;
-    let mut gid = 0;
+pub(crate) fn clock_ticks_per_sec() -> u64 {
+    match unsafe { libc::sysconf(libc::_SC_CLK_TCK) } {
+        ticks if ticks > 0 => ticks as u64,
+        _ => 100,
+    }
+}
+
+/// Whether a process with `pid` exists, whoever owns it.
+pub fn pid_alive(pid: u32) -> bool {
+    // 0 and negative values address process groups, not a process.
+    let pid = match libc::pid_t::try_from(pid) {
+        Ok(pid) if pid > 0 => pid,
+        _ => return false,
+    };
+    // Signal 0 only checks for existence; EPERM means someone else's process.
+    let res = unsafe { libc::kill(pid, 0) };
+    res == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// Maps the init PID of every running Docker container to the container.
+pub fn load_containers() -> HashMap<u32, Container> {
+    let docker = Docker::new();
+    let ctr_vec = docker.inspect_all();
+    let mut ctr_map: HashMap<u32, Container> = HashMap::new();
+    for ctr in ctr_vec {
+        ctr_map.insert(ctr.state.pid, ctr);
+    }
+    ctr_map
+}
+
+fn session(pid: Pid) -> Option<libc::pid_t> {
+    let sid = unsafe { libc::getsid(libc::pid_t::try_from(pid.as_u32()).ok()?) };
+    (sid >= 0).then_some(sid)
+}
+
+/// Owner, container and leader of the GPU process `pid`.
+struct Ancestry {
+    uid: u32,
+    gid: u32,
+    ctr_pid: Option<u32>,
+    leader: u32,
+}
+
+fn get_process(sys: &mut System, pid: u32, ctr_map: &Option<HashMap<u32, Container>>) -> Ancestry {
+    let mut owner = None;
     let mut ctr_pid = None;
+    let mut leader = pid;
+    let mut in_session = true;
     //
-    let mut cur_pid = Pid::from(i32::try_from(pid).unwrap());
-    let proc = sys.process(cur_pid);
+    let mut cur_pid = Pid::from_u32(pid);
+    // only the GPU processes and their ancestors are refreshed
+    sys.refresh_process(cur_pid);
+    let sid = session(cur_pid);
+    let proc_uid = sys.process(cur_pid).map(|p| p.uid);
     //
-    let mut cur_p = proc;
-    loop {
-        match cur_p {
-            Some(p) => {
-                // TODO:
-                uid = if p.uid != 0 { p.uid } else { uid };
-                gid = if p.uid != 0 { p.gid } else { gid };
-                if ctr_map.is_some() && ctr_pid.is_none() {
-                    let cur_pid_u32 = cur_pid.as_u32();
-                    if ctr_map.as_ref().unwrap().contains_key(&cur_pid_u32) {
-                        ctr_pid = Some(cur_pid_u32);
-                        break;
-                    }
-                }
-                match p.parent() {
-                    Some(ppid) => {
-                        cur_pid = ppid;
-                        cur_p = sys.process(cur_pid);
-                    }
-                    None => break,
-                }
+    while let Some(p) = sys.process(cur_pid) {
+        if owner.is_none() && p.uid != 0 {
+            owner = Some((p.uid, p.gid));
+        }
+        in_session = in_session && sid.is_some() && session(cur_pid) == sid;
+        // The session leader is the shell every launch from it shares.
+        let is_session_leader = sid == libc::pid_t::try_from(cur_pid.as_u32()).ok();
+        if in_session && !is_session_leader && Some(p.uid) == proc_uid {
+            leader = cur_pid.as_u32();
+        }
+        if let Some(map) = ctr_map {
+            let cur_pid_u32 = cur_pid.as_u32();
+            if map.contains_key(&cur_pid_u32) {
+                ctr_pid = Some(cur_pid_u32);
+                break;
+            }
+        }
+        match p.parent() {
+            Some(ppid) => {
+                cur_pid = ppid;
+                sys.refresh_process(cur_pid);
             }
             None => break,
         }
     }
-    (uid, gid, ctr_pid)
+    let (uid, gid) = match (owner, sys.process(Pid::from_u32(pid))) {
+        (Some(owner), _) => owner,
+        (None, Some(p)) => (p.uid, p.gid),
+        (None, None) => (0, 0),
+    };
+    Ancestry {
+        uid,
+        gid,
+        ctr_pid,
+        leader,
+    }
 }
-impl<'a> ProcessData<'a> {
-    pub fn load(gpu_info: &'a nv_gpu::GPUInfo, docker_support: bool) -> Self {
+
+impl ProcessData {
+    pub fn load(
+        sys: &mut System,
+        devices: &[Device],
+        ctr_map: Option<HashMap<u32, Container>>,
+    ) -> Self {
         let mut pd = ProcessData {
-            sys: System::new_all(),
             gpu_procs: vec![],
-            ctr_map: if docker_support {
-                let docker = Docker::new();
-                let ctr_vec = docker.inspect_all();
-                let mut ctr_map: HashMap<u32, Container> = HashMap::new();
-                for ctr in ctr_vec {
-                    ctr_map.insert(ctr.state.pid, ctr);
-                }
-                Some(ctr_map)
-            } else {
-                None
-            },
-            gpu_info,
+            ctr_map,
         };
-        for (i, gpu) in pd.gpu_info.gpus.iter().enumerate() {
-            match &gpu.processes.items {
-                Some(items) => {
-                    for proc in items.iter() {
-                        let pid = proc.pid;
-                        let (uid, gid, ctr_pid) = get_process(&pd.sys, pid, &pd.ctr_map);
-                        pd.gpu_procs.push(GProcess {
-                            gpu_id: u8::try_from(i).unwrap(),
-                            pid,
-                            uid,
-                            gid,
-                            ctr_pid,
-                            gpu_info: proc,
-                        })
-                    }
-                }
-                _ => (),
+        for gpu in devices.iter() {
+            for proc in gpu.processes.iter() {
+                let pid = proc.pid;
+                let ancestry = get_process(sys, pid, &pd.ctr_map);
+                let host = sys.process(Pid::from_u32(pid));
+                pd.gpu_procs.push(GProcess {
+                    gpu_id: gpu.index,
+                    pid,
+                    uid: ancestry.uid,
+                    gid: ancestry.gid,
+                    user: None,
+                    group: None,
+                    ctr_pid: ancestry.ctr_pid,
+                    ns_pids: vec![],
+                    owner: None,
+                    slurm_job: None,
+                    pod: None,
+                    name: host.map(|p| p.name().to_string()).unwrap_or_default(),
+                    cmdline: host.map(|p| p.cmd().to_vec()).unwrap_or_default(),
+                    start_time: host
+                        .and_then(|p| Local.timestamp_opt(p.start_time() as i64, 0).single()),
+                    elapsed: host.map(|p| p.run_time()),
+                    cpu_percent: None,
+                    // sysinfo reports kilobytes.
+                    rss: host.map(|p| p.memory() * 1024),
+                    leader: ancestry.leader,
+                    leader_name: sys
+                        .process(Pid::from_u32(ancestry.leader))
+                        .map(|p| p.name().to_string())
+                        .unwrap_or_default(),
+                    gpu_info: proc.clone(),
+                })
             }
         }
         pd
@@ -119,4 +312,11 @@ impl<'a> ProcessData<'a> {
     pub fn items(&self) -> &Vec<GProcess> {
         &self.gpu_procs
     }
+
+    pub fn containers(&self) -> Vec<Container> {
+        match &self.ctr_map {
+            Some(map) => map.values().cloned().collect(),
+            None => vec![],
+        }
+    }
 }
@@ -0,0 +1,141 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::cgroup::{classify, Owner};
+
+/// Where process details are read from; `/proc` unless a fixture tree
+/// stands in for it.
+#[derive(Debug, Clone)]
+pub struct ProcFs {
+    root: PathBuf,
+}
+
+impl Default for ProcFs {
+    fn default() -> Self {
+        ProcFs::new("/proc")
+    }
+}
+
+impl ProcFs {
+    pub fn new(root: impl AsRef<Path>) -> Self {
+        ProcFs {
+            root: root.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    fn read(&self, pid: u32, file: &str) -> Option<String> {
+        fs::read_to_string(self.root.join(pid.to_string()).join(file)).ok()
+    }
+
+    /// The value of `key` in the environment `pid` started with; only
+    /// readable for the owner's processes, or by root.
+    pub fn environ(&self, pid: u32, key: &str) -> Option<String> {
+        let environ = fs::read(self.root.join(pid.to_string()).join("environ")).ok()?;
+        environ.split(|b| *b == 0).find_map(|var| {
+            let val = var.strip_prefix(key.as_bytes())?.strip_prefix(b"=")?;
+            Some(String::from_utf8_lossy(val).into_owned())
+        })
+    }
+
+    /// CPU time `pid` has used, user and system, in clock ticks.
+    pub fn cpu_ticks(&self, pid: u32) -> Option<u64> {
+        let stat = self.read(pid, "stat")?;
+        // The command may contain spaces and parentheses; fields follow the
+        // last `)`, starting with the state, which is field 3.
+        let mut fields = stat[stat.rfind(')')? + 1..].split_whitespace().skip(11);
+        let utime = fields.next()?.parse::<u64>().ok()?;
+        let stime = fields.next()?.parse::<u64>().ok()?;
+        Some(utime + stime)
+    }
+
+    /// When `pid` started, in clock ticks after boot. Together with
+    /// [`ProcFs::boot_id`] it tells a process from a later one that reused
+    /// its PID.
+    pub fn start_time(&self, pid: u32) -> Option<u64> {
+        let stat = self.read(pid, "stat")?;
+        // `starttime` is field 22; see `cpu_ticks`.
+        stat[stat.rfind(')')? + 1..]
+            .split_whitespace()
+            .nth(19)?
+            .parse()
+            .ok()
+    }
+
+    /// Identifies the running boot of the host.
+    pub fn boot_id(&self) -> Option<String> {
+        let id = fs::read_to_string(self.root.join("sys/kernel/random/boot_id")).ok()?;
+        Some(id.trim().to_string())
+    }
+
+    /// The PIDs of `pid` in each PID namespace it is in, from the host's
+    /// outwards to its own; just `pid` outside any container.
+    ///
+    /// ```
+    /// use gmon::procfs::ProcFs;
+    ///
+    /// let root = std::env::temp_dir().join(format!("gmon-nspid-{}", std::process::id()));
+    /// std::fs::create_dir_all(root.join("4242"))?;
+    /// std::fs::write(root.join("4242/status"), "Name:\tpython\nNSpid:\t4242\t57\n")?;
+    /// assert_eq!(ProcFs::new(&root).ns_pids(4242), vec![4242, 57]);
+    /// # std::fs::remove_dir_all(&root).ok();
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn ns_pids(&self, pid: u32) -> Vec<u32> {
+        self.read(pid, "status")
+            .and_then(|status| {
+                let line = status
+                    .lines()
+                    .find_map(|line| line.strip_prefix("NSpid:"))?;
+                line.split_whitespace()
+                    .map(|pid| pid.parse::<u32>().ok())
+                    .collect::<Option<Vec<_>>>()
+            })
+            .unwrap_or_default()
+    }
+
+    /// Control group paths of `pid`, the unified hierarchy first.
+    pub fn cgroups(&self, pid: u32) -> Vec<String> {
+        let text = match self.read(pid, "cgroup") {
+            Some(text) => text,
+            None => return vec![],
+        };
+        // Lines are `hierarchy:controllers:path`; the path may contain `:`.
+        let mut entries = text
+            .lines()
+            .filter_map(|line| {
+                let mut fields = line.splitn(3, ':');
+                let hierarchy = fields.next()?;
+                fields.next()?;
+                Some((hierarchy != "0", fields.next()?.to_string()))
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by_key(|(v1, _)| *v1);
+        entries.into_iter().map(|(_, path)| path).collect()
+    }
+
+    /// What `pid` runs under, judged by the first of its control groups that
+    /// says.
+    ///
+    /// ```
+    /// use gmon::cgroup::Owner;
+    /// use gmon::procfs::ProcFs;
+    ///
+    /// let root = std::env::temp_dir().join(format!("gmon-proc-{}", std::process::id()));
+    /// std::fs::create_dir_all(root.join("42"))?;
+    /// std::fs::write(
+    ///     root.join("42/cgroup"),
+    ///     "0::/system.slice/slurmstepd.scope/job_1234/step_0/user/task_0\n",
+    /// )?;
+    /// let owner = ProcFs::new(&root).owner(42);
+    /// assert_eq!(owner, Some(Owner::Slurm { job: 1234, step: Some(String::from("0")) }));
+    /// # std::fs::remove_dir_all(&root).ok();
+    /// # Ok::<(), std::io::Error>(())
+    /// ```
+    pub fn owner(&self, pid: u32) -> Option<Owner> {
+        self.cgroups(pid).iter().find_map(|path| classify(path))
+    }
+}
@@ -0,0 +1,374 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::OpenOptions;
+use std::os::unix::process::CommandExt;
+use std::process::{Child, Command, Stdio};
+use std::thread::sleep;
+use std::time::Duration;
+
+use chrono::Local;
+use gmon::claim::{Claim, ClaimError, ClaimStore};
+use gmon::collector::Collector;
+use gmon::procfs::ProcFs;
+use gmon::select::{free_gpus, Criteria};
+use gmon::spool::{Job, JobState, Spool, SpoolError};
+use gmon::vendors::error::BackendError;
+use gmon::vendors::traits::{GpuInfo, GpuProviderInfo};
+use thiserror::Error;
+
+use crate::cli::SubmitArgs;
+
+/// Runs the command given as arguments, then records its exit status in the
+/// file named first; the status outlives the worker that started the job.
+const WRAPPER: &str = r#"status=$1; shift; "$@"; code=$?; echo $code > "$status"; exit $code"#;
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error(transparent)]
+    Backend(#[from] BackendError),
+    #[error(transparent)]
+    Spool(#[from] SpoolError),
+    #[error("job {0} has already finished")]
+    Finished(u64),
+    #[error("failed to stop job {0}: {1}")]
+    Signal(u64, std::io::Error),
+}
+
+/// Whether the wrapper of a running job is still the process with its PID.
+fn wrapper_alive(pid: u32, start_time: Option<u64>, boot_id: Option<&str>) -> bool {
+    let procfs = ProcFs::default();
+    pid != 0
+        && boot_id.is_some()
+        && procfs.boot_id().as_deref() == boot_id
+        && start_time.is_some()
+        && procfs.start_time(pid) == start_time
+}
+
+pub fn submit(spool: &Spool, args: &SubmitArgs) -> Result<(), Error> {
+    let job = spool.submit(args.command.clone(), args.gpus, args.mem, args.max_util)?;
+    println!("{}", job.id);
+    Ok(())
+}
+
+fn state_name(state: &JobState) -> String {
+    match state {
+        JobState::Queued => String::from("queued"),
+        JobState::Running { .. } => String::from("running"),
+        JobState::Exited {
+            code: Some(code), ..
+        } => format!("exited {}", code),
+        JobState::Exited { code: None, .. } => String::from("killed"),
+        JobState::Failed { .. } => String::from("failed"),
+        JobState::Cancelled { .. } => String::from("cancelled"),
+    }
+}
+
+fn join_indices(gpus: &[u32]) -> String {
+    gpus.iter()
+        .map(|gpu| gpu.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+pub fn list(spool: &Spool, json: bool) -> Result<(), Error> {
+    let jobs = spool.jobs()?;
+    if json {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&jobs).expect("jobs are always serializable")
+        );
+        return Ok(());
+    }
+    println!(
+        "{:>5}  {:<10} {:<8} {:<12} COMMAND",
+        "ID", "STATE", "GPUS", "SUBMITTED"
+    );
+    for job in jobs {
+        let gpus = match &job.state {
+            JobState::Running { indices, .. } | JobState::Exited { indices, .. } => {
+                join_indices(indices)
+            }
+            _ => format!("{}x", job.gpus),
+        };
+        println!(
+            "{:>5}  {:<10} {:<8} {:<12} {}",
+            job.id,
+            state_name(&job.state),
+            gpus,
+            job.submitted.format("%m-%d %H:%M"),
+            job.command.join(" ")
+        );
+    }
+    Ok(())
+}
+
+/// Drops queued jobs and sends SIGTERM to the process group of running ones;
+/// the worker records how those end.
+pub fn cancel(spool: &Spool, ids: &[u64]) -> Result<(), Error> {
+    let _lock = spool.lock()?;
+    for &id in ids {
+        let mut job = spool.job(id)?;
+        match job.state {
+            JobState::Queued => {
+                job.state = JobState::Cancelled {
+                    finished: Local::now(),
+                };
+                spool.save(&job)?;
+                eprintln!("gmon: cancelled job {}", id);
+            }
+            JobState::Running {
+                pid,
+                start_time,
+                ref boot_id,
+                ..
+            } => {
+                // Its process group may belong to someone else by now.
+                if !wrapper_alive(pid, start_time, boot_id.as_deref()) {
+                    eprintln!("gmon: job {} is no longer running", id);
+                    continue;
+                }
+                if unsafe { libc::kill(-(pid as libc::pid_t), libc::SIGTERM) } != 0 {
+                    return Err(Error::Signal(id, std::io::Error::last_os_error()));
+                }
+                eprintln!("gmon: sent SIGTERM to job {}", id);
+            }
+            _ => return Err(Error::Finished(id)),
+        }
+    }
+    Ok(())
+}
+
+/// Starts `job` on `gpus`, logging to the spool.
+fn launch(spool: &Spool, job: &Job, gpus: &[u32]) -> std::io::Result<Child> {
+    let log = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(spool.log_path(job.id))?;
+    Command::new("/bin/sh")
+        .arg("-c")
+        .arg(WRAPPER)
+        .arg("sh")
+        .arg(spool.status_path(job.id))
+        .args(&job.command)
+        .current_dir(&job.cwd)
+        .env_clear()
+        .envs(&job.env)
+        .env("CUDA_VISIBLE_DEVICES", join_indices(gpus))
+        .env("CUDA_DEVICE_ORDER", "PCI_BUS_ID")
+        .env("GMON_JOB_ID", job.id.to_string())
+        .stdin(Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log)
+        // Its own group, so the job outlives a worker stopped with ^C and
+        // cancelling it reaches everything it started.
+        .process_group(0)
+        .spawn()
+}
+
+/// Records running jobs that have ended since the last look.
+fn reap(spool: &Spool, children: &mut HashMap<u64, Child>) -> Result<(), Error> {
+    let _lock = spool.lock()?;
+    for mut job in spool.jobs()? {
+        let (pid, indices, started) = match &job.state {
+            JobState::Running {
+                pid,
+                start_time,
+                boot_id,
+                indices,
+                started,
+                ..
+            } => {
+                let ended = match children.get_mut(&job.id) {
+                    Some(child) => !matches!(child.try_wait(), Ok(None)),
+                    // Started by an earlier worker; its wrapper is no child
+                    // of ours.
+                    None => !wrapper_alive(*pid, *start_time, boot_id.as_deref()),
+                };
+                if !ended {
+                    continue;
+                }
+                (*pid, indices.clone(), *started)
+            }
+            _ => continue,
+        };
+        children.remove(&job.id);
+        let code = std::fs::read_to_string(spool.status_path(job.id))
+            .ok()
+            .and_then(|text| text.trim().parse::<i32>().ok());
+        job.state = match code {
+            // A worker stopped between marking the job and starting it.
+            None if pid == 0 => JobState::Failed {
+                reason: String::from("the worker stopped while starting it"),
+                finished: Local::now(),
+            },
+            code => JobState::Exited {
+                code,
+                indices,
+                started,
+                finished: Local::now(),
+            },
+        };
+        spool.save(&job)?;
+        eprintln!("gmon: job {} {}", job.id, state_name(&job.state));
+    }
+    Ok(())
+}
+
+/// Starts the oldest queued job if enough GPUs are free, returning whether
+/// one was started. Stale claims the worker cannot remove are reported
+/// once, and added to `reported`.
+fn start_next<'a, P, G>(
+    collector: &mut Collector<'a, P>,
+    spool: &Spool,
+    store: &ClaimStore,
+    children: &mut HashMap<u64, Child>,
+    reported: &mut HashSet<String>,
+) -> Result<bool, Error>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    let jobs = spool.jobs()?;
+    let job = match jobs.iter().find(|job| job.state == JobState::Queued) {
+        Some(job) => job,
+        None => return Ok(false),
+    };
+    // Our own jobs do not occupy GPUs by the usual rules, so skip them here.
+    let taken = jobs
+        .iter()
+        .filter_map(|job| match &job.state {
+            JobState::Running { indices, .. } => Some(indices.clone()),
+            _ => None,
+        })
+        .flatten()
+        .collect::<Vec<_>>();
+    let criteria = Criteria {
+        min_free_memory: job.min_free_memory,
+        max_utilization: job.max_utilization,
+        uid: Some(unsafe { libc::getuid() }),
+        owner: ProcFs::default().owner(std::process::id()),
+        own_claims: false,
+    };
+    let samples = [collector.sample()?];
+    let free = free_gpus(&samples, &criteria)
+        .into_iter()
+        .filter(|gpu| !taken.contains(&gpu.index))
+        .take(job.gpus)
+        .collect::<Vec<_>>();
+    if free.len() < job.gpus {
+        for claim in samples[0]
+            .claims
+            .iter()
+            .filter(|claim| store.is_stale(claim))
+        {
+            if reported.insert(claim.uuid.clone()) {
+                eprintln!(
+                    "gmon: {} stays held by a stale claim of {} until they or root release it",
+                    claim.uuid, claim.user
+                );
+            }
+        }
+        return Ok(false);
+    }
+    let uuids = free
+        .iter()
+        .filter_map(|gpu| gpu.uuid.clone())
+        .collect::<Vec<_>>();
+    // Claim before starting so nobody else picks the GPUs meanwhile; the
+    // claims are handed to the job once it has a PID.
+    let note = format!("queue job {}", job.id);
+    let claims = uuids
+        .iter()
+        .map(|uuid| Claim {
+            note: Some(note.clone()),
+            ..Claim::new(uuid, std::process::id())
+        })
+        .collect::<Vec<_>>();
+    let claimed = match store.acquire(&claims) {
+        Ok(()) => true,
+        Err(ClaimError::Held { .. }) => return Ok(false),
+        Err(e) => {
+            eprintln!("gmon: starting job {} without claims: {}", job.id, e);
+            false
+        }
+    };
+    let _lock = spool.lock()?;
+    let mut job = spool.job(job.id)?;
+    if job.state != JobState::Queued {
+        // Cancelled meanwhile.
+        for uuid in uuids.iter().filter(|_| claimed) {
+            let _ = store.release(uuid, unsafe { libc::getuid() });
+        }
+        return Ok(true);
+    }
+    let indices = free.iter().map(|gpu| gpu.index).collect::<Vec<_>>();
+    // Mark the job first, so a worker stopped before the next save cannot
+    // start it a second time.
+    job.state = JobState::Running {
+        pid: 0,
+        start_time: None,
+        boot_id: None,
+        indices: indices.clone(),
+        uuids: uuids.clone(),
+        started: Local::now(),
+    };
+    spool.save(&job)?;
+    match launch(spool, &job, &indices) {
+        Ok(child) => {
+            for uuid in uuids.iter().filter(|_| claimed) {
+                let _ = store.hand_over(uuid, child.id());
+            }
+            let procfs = ProcFs::default();
+            job.state = JobState::Running {
+                pid: child.id(),
+                start_time: procfs.start_time(child.id()),
+                boot_id: procfs.boot_id(),
+                indices: indices.clone(),
+                uuids,
+                started: Local::now(),
+            };
+            eprintln!(
+                "gmon: job {} started on GPUs {}",
+                job.id,
+                join_indices(&indices)
+            );
+            children.insert(job.id, child);
+        }
+        Err(e) => {
+            for uuid in uuids.iter().filter(|_| claimed) {
+                let _ = store.release(uuid, unsafe { libc::getuid() });
+            }
+            job.state = JobState::Failed {
+                reason: e.to_string(),
+                finished: Local::now(),
+            };
+            eprintln!("gmon: job {} failed to start: {}", job.id, e);
+        }
+    }
+    spool.save(&job)?;
+    Ok(true)
+}
+
+/// Works through the queue until killed. Jobs left running by an earlier
+/// worker are picked up again from the spool.
+pub fn run<'a, P, G>(
+    collector: &mut Collector<'a, P>,
+    spool: &Spool,
+    store: &ClaimStore,
+    poll: Duration,
+) -> Result<(), Error>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    let _worker = spool.lock_worker()?;
+    eprintln!("gmon: working through {}", spool.dir().display());
+    let mut children = HashMap::new();
+    let mut reported = HashSet::new();
+    loop {
+        reap(spool, &mut children)?;
+        // Start as many jobs as fit before waiting again.
+        if !start_next(collector, spool, store, &mut children, &mut reported)? {
+            sleep(poll);
+        }
+    }
+}
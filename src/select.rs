@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use serde::Serialize;
+
+use crate::cgroup::Owner;
+use crate::collector::Collector;
+use crate::process::GProcess;
+use crate::snapshot::Snapshot;
+use crate::vendors::error::BackendError;
+use crate::vendors::traits::{GpuInfo, GpuProviderInfo};
+use crate::vendors::types::Topology;
+
+/// What makes a GPU free.
+#[derive(Debug, Clone)]
+pub struct Criteria {
+    /// Free memory needed, in MiB.
+    pub min_free_memory: u64,
+    /// Highest utilization tolerated, in percent.
+    pub max_utilization: u32,
+    /// The user asking. Processes of any other user occupy a GPU; `None`
+    /// lets every process occupy it.
+    pub uid: Option<u32>,
+    /// The control group owner of the caller. Processes in a container or
+    /// pod occupy a GPU whatever their uid, unless they are in this one.
+    pub owner: Option<Owner>,
+    /// Whether GPUs claimed by `uid` count as available, as when choosing
+    /// GPUs for work the caller claimed them for. Selections that go on to
+    /// claim must leave this off.
+    pub own_claims: bool,
+}
+
+impl Default for Criteria {
+    fn default() -> Self {
+        Criteria {
+            min_free_memory: 0,
+            max_utilization: 100,
+            uid: None,
+            owner: None,
+            own_claims: false,
+        }
+    }
+}
+
+/// A GPU that met the criteria in every sample, with its worst readings.
+#[derive(Debug, Clone, Serialize)]
+pub struct FreeGpu {
+    pub index: u32,
+    pub uuid: Option<String>,
+    pub name: Option<String>,
+    /// Lowest free memory seen, in MiB.
+    pub memory_free: u64,
+    /// Highest utilization seen, in percent.
+    pub utilization: u32,
+}
+
+/// Whether `proc` keeps a GPU from being free for the caller `criteria`
+/// describes.
+///
+/// Containers run as any uid, so the uid of a process in one, told by its
+/// control group or by a container lookup, proves nothing: root in another
+/// user's container looks like root outside it.
+pub fn occupies(proc: &GProcess, criteria: &Criteria) -> bool {
+    let uid = match criteria.uid {
+        Some(uid) => uid,
+        None => return true,
+    };
+    let contained = proc.ctr_pid.is_some()
+        || matches!(
+            proc.owner,
+            Some(Owner::Container { .. } | Owner::Pod { .. })
+        );
+    if contained && (proc.owner.is_none() || proc.owner != criteria.owner) {
+        return true;
+    }
+    proc.uid != uid
+}
+
+/// GPUs that were available, unclaimed and met `criteria` in each of
+/// `samples`, in index order.
+///
+/// ```
+/// use gmon::select::{free_gpus, Criteria};
+/// use gmon::vendors::fake::{FakeGpu, FakeGpuProvider};
+/// use gmon::Snapshot;
+///
+/// let mut busy = FakeGpu::idle(1);
+/// busy.utilization = 80;
+/// let provider = FakeGpuProvider::new(vec![FakeGpu::idle(0), busy]);
+/// let snapshot = Snapshot::collect(&provider, false)?;
+/// let criteria = Criteria {
+///     max_utilization: 10,
+///     ..Criteria::default()
+/// };
+/// let free = free_gpus(&[snapshot], &criteria);
+/// assert_eq!(free.iter().map(|gpu| gpu.index).collect::<Vec<_>>(), vec![0]);
+/// # Ok::<(), gmon::BackendError>(())
+/// ```
+pub fn free_gpus(samples: &[Snapshot], criteria: &Criteria) -> Vec<FreeGpu> {
+    let mut found = BTreeMap::<u32, (usize, FreeGpu)>::new();
+    for snapshot in samples {
+        for device in snapshot.devices.iter().filter(|d| d.is_available()) {
+            let (memory, utilization) = match (&device.memory, &device.utilization) {
+                (Some(memory), Some(utilization)) => (memory, utilization.val),
+                _ => continue,
+            };
+            let claimed = snapshot.claims.iter().any(|claim| {
+                let own = criteria.own_claims && criteria.uid == Some(claim.uid);
+                !own && device
+                    .uuid
+                    .as_ref()
+                    .is_some_and(|uuid| uuid.val == claim.uuid)
+            });
+            if claimed {
+                continue;
+            }
+            let memory_free = memory.total.val.saturating_sub(memory.used.val);
+            let occupied = snapshot
+                .processes
+                .iter()
+                .filter(|proc| proc.gpu_id == device.index)
+                .any(|proc| occupies(proc, criteria));
+            if occupied
+                || memory_free < criteria.min_free_memory
+                || utilization > criteria.max_utilization
+            {
+                continue;
+            }
+            let entry = found.entry(device.index).or_insert_with(|| {
+                (
+                    0,
+                    FreeGpu {
+                        index: device.index,
+                        uuid: device.uuid.as_ref().map(|v| v.val.clone()),
+                        name: device.name.as_ref().map(|v| v.val.clone()),
+                        memory_free,
+                        utilization,
+                    },
+                )
+            });
+            entry.0 += 1;
+            entry.1.memory_free = entry.1.memory_free.min(memory_free);
+            entry.1.utilization = entry.1.utilization.max(utilization);
+        }
+    }
+    found
+        .into_values()
+        .filter(|(seen, _)| *seen == samples.len())
+        .map(|(_, gpu)| gpu)
+        .collect()
+}
+
+/// Picks `count` of `gpus` that sit as close together as possible, in index
+/// order, or `None` if there are fewer than `count`.
+///
+/// Each GPU in turn seeds a set that grows by the GPU nearest to all already
+/// chosen; the set whose farthest pair is closest wins, ties going to the
+/// lowest indices. `topology` returns `None` for pairs of unknown distance,
+/// which rank behind every known link. The search is greedy rather than
+/// exhaustive, which finds the best set on tree-shaped PCIe layouts.
+///
+/// ```
+/// use gmon::select::{closest_gpus, free_gpus, Criteria};
+/// use gmon::vendors::fake::FakeGpuProvider;
+/// use gmon::vendors::types::Topology;
+/// use gmon::Snapshot;
+///
+/// let provider = FakeGpuProvider::with_idle_devices(4);
+/// let snapshot = Snapshot::collect(&provider, false)?;
+/// let free = free_gpus(&[snapshot], &Criteria::default());
+/// let picked = closest_gpus(&free, 2, |a, b| match (a.min(b), a.max(b)) {
+///     (1, 2) => Some(Topology::NvLink),
+///     _ => Some(Topology::System),
+/// });
+/// let indices: Vec<u32> = picked.unwrap().iter().map(|gpu| gpu.index).collect();
+/// assert_eq!(indices, vec![1, 2]);
+/// # Ok::<(), gmon::BackendError>(())
+/// ```
+pub fn closest_gpus(
+    gpus: &[FreeGpu],
+    count: usize,
+    topology: impl Fn(u32, u32) -> Option<Topology>,
+) -> Option<Vec<FreeGpu>> {
+    if gpus.len() < count {
+        return None;
+    }
+    if count == 0 {
+        return Some(vec![]);
+    }
+    let n = gpus.len();
+    // Rank pairs once; unknown distances sort after `Topology::System`.
+    let mut distance = vec![vec![None; n]; n];
+    for i in 0..n {
+        for j in i + 1..n {
+            let level = topology(gpus[i].index, gpus[j].index);
+            distance[i][j] = level;
+            distance[j][i] = level;
+        }
+    }
+    let rank = |i: usize, j: usize| distance[i][j].map_or(usize::MAX, |level| level as usize);
+    let mut best: Option<(usize, Vec<usize>)> = None;
+    for seed in 0..n {
+        let mut chosen = vec![seed];
+        let mut worst = 0;
+        while chosen.len() < count {
+            let (next, cost) = (0..n)
+                .filter(|i| !chosen.contains(i))
+                .map(|i| (i, chosen.iter().map(|&j| rank(i, j)).max().unwrap_or(0)))
+                .min_by_key(|&(i, cost)| (cost, i))
+                .expect("enough candidates remain");
+            chosen.push(next);
+            worst = worst.max(cost);
+        }
+        chosen.sort_unstable();
+        let better = match &best {
+            Some((best_worst, best_chosen)) => (worst, &chosen) < (*best_worst, best_chosen),
+            None => true,
+        };
+        if better {
+            best = Some((worst, chosen));
+        }
+    }
+    let chosen = best.map(|(_, chosen)| chosen).unwrap_or_default();
+    Some(chosen.into_iter().map(|i| gpus[i].clone()).collect())
+}
+
+/// Samples every `every` until `window` has passed, always at least once.
+pub fn sample_window<'a, P, G>(
+    collector: &mut Collector<'a, P>,
+    window: Duration,
+    every: Duration,
+) -> Result<Vec<Snapshot>, BackendError>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    let end = Instant::now() + window;
+    let mut samples = vec![collector.sample()?];
+    while Instant::now() + every <= end {
+        sleep(every);
+        samples.push(collector.sample()?);
+    }
+    Ok(samples)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{occupies, Criteria};
+    use crate::cgroup::Owner;
+    use crate::process::GProcess;
+    use crate::snapshot::Snapshot;
+    use crate::vendors::fake::{FakeGpu, FakeGpuProvider};
+    use crate::vendors::types::ProcessInfo;
+
+    /// A process of the caller on GPU 0.
+    fn own_process() -> GProcess {
+        let mut gpu = FakeGpu::idle(0);
+        gpu.processes = vec![ProcessInfo {
+            pid: std::process::id(),
+            used_memory: None,
+        }];
+        let provider = FakeGpuProvider::new(vec![gpu]);
+        let mut snapshot = Snapshot::collect(&provider, false).unwrap();
+        // Wherever the tests run, the process is in no container.
+        GProcess {
+            owner: None,
+            ctr_pid: None,
+            ..snapshot.processes.remove(0)
+        }
+    }
+
+    fn caller(owner: Option<Owner>) -> Criteria {
+        Criteria {
+            uid: Some(unsafe { libc::getuid() }),
+            owner,
+            ..Criteria::default()
+        }
+    }
+
+    #[test]
+    fn counts_other_users_processes() {
+        let mut proc = own_process();
+        assert!(!occupies(&proc, &caller(None)));
+        proc.uid += 1;
+        assert!(occupies(&proc, &caller(None)));
+        assert!(occupies(&own_process(), &Criteria::default()));
+    }
+
+    #[test]
+    fn counts_containers_whatever_their_uid() {
+        let container = Owner::Container {
+            runtime: String::from("docker"),
+            id: "ab".repeat(32),
+        };
+        let mut proc = own_process();
+        proc.owner = Some(container.clone());
+        assert!(occupies(&proc, &caller(None)));
+        // Found by a container lookup only.
+        let mut proc = own_process();
+        proc.ctr_pid = Some(1);
+        assert!(occupies(&proc, &caller(None)));
+        // The caller's own container.
+        let mut proc = own_process();
+        proc.owner = Some(container.clone());
+        assert!(!occupies(&proc, &caller(Some(container))));
+
+        let mut proc = own_process();
+        proc.owner = Some(Owner::Pod {
+            uid: String::from("0f2c6a1e-1b7f-4d8e-9c3a-2a9d6b3c4e5f"),
+            container: None,
+        });
+        assert!(occupies(&proc, &caller(None)));
+    }
+}
@@ -0,0 +1,236 @@
+//! A minimal HTTP endpoint for Prometheus scrapes.
+//!
+//! Requests are handled one at a time, which keeps a single collector and
+//! its caches; scrapes are cheap enough that this is not a bottleneck.
+
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::time::Duration;
+
+use crate::collector::Collector;
+use crate::export::prometheus;
+use crate::vendors::error::BackendError;
+use crate::vendors::traits::{GpuInfo, GpuProviderInfo};
+
+/// How long a client may take to send its request or read the response.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Longest request line or header accepted, in bytes.
+const MAX_LINE: u64 = 8 * 1024;
+
+/// Most headers read from one request.
+const MAX_HEADERS: usize = 100;
+
+const TEXT: &str = "text/plain; charset=utf-8";
+
+fn respond(stream: &mut TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    // One write, so the response goes out whole even if the client hangs up.
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes())?;
+    stream.flush()
+}
+
+/// Reads one line of at most [`MAX_LINE`] bytes into `line`, and tells
+/// whether it was complete.
+fn read_line(reader: &mut impl BufRead, line: &mut String) -> io::Result<bool> {
+    line.clear();
+    reader.by_ref().take(MAX_LINE).read_line(line)?;
+    Ok(line.ends_with('\n'))
+}
+
+/// Answers one request on `stream`, calling `scrape` for the metrics text
+/// only when `/metrics` is asked for.
+pub fn handle<F>(stream: &mut TcpStream, scrape: F) -> io::Result<()>
+where
+    F: FnOnce() -> Result<String, BackendError>,
+{
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut request = String::new();
+    if !read_line(&mut reader, &mut request)? {
+        return respond(stream, "414 URI Too Long", TEXT, "request line too long\n");
+    }
+    // Drain the headers; the request body, if any, is ignored.
+    let mut line = String::new();
+    let mut headers = 0;
+    loop {
+        if !read_line(&mut reader, &mut line)? || headers == MAX_HEADERS {
+            return respond(
+                stream,
+                "431 Request Header Fields Too Large",
+                TEXT,
+                "headers too large\n",
+            );
+        }
+        if line.trim_end().is_empty() {
+            break;
+        }
+        headers += 1;
+    }
+    let mut parts = request.split_whitespace();
+    let (method, path) = (parts.next().unwrap_or(""), parts.next().unwrap_or(""));
+    match (method, path.split('?').next().unwrap_or("")) {
+        ("GET", "/metrics") => match scrape() {
+            Ok(text) => respond(stream, "200 OK", prometheus::CONTENT_TYPE, &text),
+            Err(e) => respond(
+                stream,
+                "500 Internal Server Error",
+                TEXT,
+                &format!("{}\n", e),
+            ),
+        },
+        ("GET", "/") => respond(stream, "200 OK", TEXT, "gmon exporter, see /metrics\n"),
+        ("GET", _) => respond(stream, "404 Not Found", TEXT, "not found\n"),
+        _ => respond(
+            stream,
+            "405 Method Not Allowed",
+            TEXT,
+            "method not allowed\n",
+        ),
+    }
+}
+
+/// Serves `/metrics` on `listener`, sampling `collector` on every scrape.
+///
+/// Errors on single connections are reported on stderr and do not stop the
+/// server.
+pub fn run<'a, P, G>(listener: &TcpListener, collector: &mut Collector<'a, P>) -> io::Result<()>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    for stream in listener.incoming() {
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(_) => continue,
+        };
+        let scrape = || {
+            collector
+                .sample()
+                .map(|snapshot| prometheus::render(&snapshot))
+        };
+        if let Err(e) = handle(&mut stream, scrape) {
+            eprintln!("gmon: {}", e);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Read, Write};
+    use std::net::{Shutdown, TcpListener, TcpStream};
+    use std::thread;
+
+    use super::handle;
+    use crate::collector::Collector;
+    use crate::docker::container::{Container, State};
+    use crate::export::prometheus;
+    use crate::vendors::error::BackendError;
+    use crate::vendors::fake::{FakeGpu, FakeGpuProvider};
+    use crate::vendors::types::{ProcessInfo, Value};
+
+    /// Sends `request` to a fresh listener and returns the raw response.
+    fn exchange<F>(request: &[u8], scrape: F) -> String
+    where
+        F: FnOnce() -> Result<String, BackendError>,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let request = request.to_vec();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            // The server may answer before reading everything we send.
+            stream.write_all(&request).ok();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            response
+        });
+        let (mut stream, _) = listener.accept().unwrap();
+        handle(&mut stream, scrape).unwrap();
+        // Closing with unread input would reset the connection; let the
+        // client read the response and hang up first.
+        stream.shutdown(Shutdown::Write).unwrap();
+        io::copy(&mut stream, &mut io::sink()).ok();
+        client.join().unwrap()
+    }
+
+    #[test]
+    fn scrapes_process_metrics() {
+        let pid = std::process::id();
+        let mut gpu = FakeGpu::idle(0);
+        gpu.memory_used = 512;
+        gpu.processes = vec![ProcessInfo {
+            pid,
+            used_memory: Some(Value::from(512)),
+        }];
+        let provider = FakeGpuProvider::new(vec![gpu]);
+        let mut collector = Collector::new(&provider, false);
+        let (mut command, mut user) = (String::new(), String::new());
+        let response = exchange(b"GET /metrics HTTP/1.1\r\nHost: gmon\r\n\r\n", || {
+            let mut snapshot = collector.sample()?;
+            // Stand in for docker: put the process in a container.
+            snapshot.containers = vec![Container {
+                id: String::from("c0ffee"),
+                name: String::from("trainer"),
+                state: State { pid },
+            }];
+            snapshot.processes[0].ctr_pid = Some(pid);
+            command = snapshot.processes[0].name.clone();
+            user = snapshot.processes[0].user_or_uid();
+            Ok(prometheus::render(&snapshot))
+        });
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{}", response);
+        assert!(response.contains(&format!("Content-Type: {}\r\n", prometheus::CONTENT_TYPE)));
+        let used = 512 * 1024 * 1024;
+        for line in [
+            format!(
+                "gmon_process_memory_used_bytes{{gpu=\"0\",pid=\"{}\",command=\"{}\",user=\"{}\",container=\"trainer\"}} {}",
+                pid,
+                command,
+                user,
+                used
+            ),
+            format!(
+                "gmon_container_memory_used_bytes{{gpu=\"0\",container=\"trainer\"}} {}",
+                used
+            ),
+            format!(
+                "gmon_user_memory_used_bytes{{gpu=\"0\",user=\"{}\"}} {}",
+                user, used
+            ),
+        ] {
+            assert!(response.lines().any(|l| l == line), "{} not in\n{}", line, response);
+        }
+    }
+
+    #[test]
+    fn answers_other_paths() {
+        let unused = || panic!("only /metrics samples");
+        let response = exchange(b"GET /nope HTTP/1.1\r\n\r\n", unused);
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found\r\n"),
+            "{}",
+            response
+        );
+        let response = exchange(b"POST /metrics HTTP/1.1\r\n\r\n", unused);
+        assert!(response.starts_with("HTTP/1.1 405 "), "{}", response);
+    }
+
+    #[test]
+    fn rejects_long_request_lines() {
+        let mut request = b"GET /".to_vec();
+        request.resize(request.len() + 64 * 1024, b'a');
+        request.extend(b" HTTP/1.1\r\n\r\n");
+        let response = exchange(&request, || panic!("only /metrics samples"));
+        assert!(response.starts_with("HTTP/1.1 414 "), "{}", response);
+    }
+}
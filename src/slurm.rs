@@ -0,0 +1,264 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::time::{Duration, Instant};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::cgroup::Owner;
+use crate::procfs::ProcFs;
+
+/// `squeue` fields, the free-form job name last.
+const FORMAT: &str = "%A|%u|%P|%l|%T|%j";
+
+/// How long what `squeue` said about a job holds, so changes of state show.
+const TTL: Duration = Duration::from_secs(30);
+
+/// How long to leave `squeue` alone after it failed, since it may take
+/// seconds to time out while the controller is down.
+const RETRY: Duration = Duration::from_secs(10);
+
+/// A Slurm job and, if `squeue` knew it, its details.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct SlurmJob {
+    pub id: u64,
+    pub name: Option<String>,
+    pub user: Option<String>,
+    pub partition: Option<String>,
+    /// As `squeue` prints it, e.g. `1-00:00:00` or `UNLIMITED`.
+    pub time_limit: Option<String>,
+    pub state: Option<String>,
+}
+
+impl SlurmJob {
+    fn bare(id: u64) -> Self {
+        SlurmJob {
+            id,
+            name: None,
+            user: None,
+            partition: None,
+            time_limit: None,
+            state: None,
+        }
+    }
+
+    /// Parses a line of `squeue --format` output in [`FORMAT`].
+    fn parse(line: &str) -> Option<Self> {
+        let mut fields = line.trim_end_matches('\n').splitn(6, '|');
+        let id = fields.next()?.trim().parse().ok()?;
+        let mut next = || Some(fields.next()?.trim().to_string()).filter(|val| !val.is_empty());
+        Some(SlurmJob {
+            id,
+            user: next(),
+            partition: next(),
+            time_limit: next(),
+            state: next(),
+            name: next(),
+        })
+    }
+}
+
+/// The job `pid` runs in: from its control group, or else from
+/// `SLURM_JOB_ID` in its environment.
+pub fn job_id(procfs: &ProcFs, pid: u32, owner: Option<&Owner>) -> Option<u64> {
+    match owner {
+        Some(Owner::Slurm { job, .. }) => Some(*job),
+        _ => procfs.environ(pid, "SLURM_JOB_ID")?.parse().ok(),
+    }
+}
+
+/// Looks up jobs with `squeue`, remembering what it learnt for a while.
+///
+/// Jobs `squeue` does not know, or all of them if it cannot be run, are
+/// reported by ID alone; failed lookups are tried again later.
+///
+/// ```
+/// use gmon::slurm::Slurm;
+///
+/// let mut slurm = Slurm::new("/nonexistent/squeue");
+/// let jobs = slurm.jobs(&[42]);
+/// assert_eq!(jobs[&42].id, 42);
+/// assert_eq!(jobs[&42].name, None);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Slurm {
+    squeue: PathBuf,
+    ttl: Duration,
+    retry: Duration,
+    /// Jobs and when `squeue` described them.
+    known: HashMap<u64, (SlurmJob, Instant)>,
+    /// When `squeue` last failed.
+    failed: Option<Instant>,
+}
+
+impl Slurm {
+    pub fn new(squeue: impl AsRef<Path>) -> Self {
+        Slurm {
+            squeue: squeue.as_ref().to_path_buf(),
+            ttl: TTL,
+            retry: RETRY,
+            known: HashMap::new(),
+            failed: None,
+        }
+    }
+
+    fn query(&self, ids: &[u64]) -> Option<Vec<SlurmJob>> {
+        let list = ids
+            .iter()
+            .map(|id| id.to_string())
+            .collect::<Vec<_>>()
+            .join(",");
+        let out = Command::new(&self.squeue)
+            .args(["--noheader", "--jobs", &list, "--format", FORMAT])
+            .stdin(Stdio::null())
+            .stderr(Stdio::null())
+            .output()
+            .ok()?;
+        out.status.success().then(|| {
+            String::from_utf8_lossy(&out.stdout)
+                .lines()
+                .filter_map(SlurmJob::parse)
+                .collect()
+        })
+    }
+
+    /// Details of the jobs `ids`. Jobs not asked about are forgotten.
+    pub fn jobs(&mut self, ids: &[u64]) -> HashMap<u64, SlurmJob> {
+        let now = Instant::now();
+        let ttl = self.ttl;
+        self.known
+            .retain(|id, (_, at)| ids.contains(id) && now.duration_since(*at) < ttl);
+        let mut missing = ids
+            .iter()
+            .copied()
+            .filter(|id| !self.known.contains_key(id))
+            .collect::<Vec<_>>();
+        missing.sort_unstable();
+        missing.dedup();
+        let resting = self
+            .failed
+            .is_some_and(|at| now.duration_since(at) < self.retry);
+        if !missing.is_empty() && !resting {
+            // squeue fails as a whole if any job has left its memory, so
+            // retry those one by one. Jobs it answered for without listing
+            // them are gone and known by ID alone.
+            let wanted = missing.len();
+            let answered = match self.query(&missing) {
+                Some(found) => vec![(missing, found)],
+                None if missing.len() > 1 => missing
+                    .iter()
+                    .filter_map(|id| Some((vec![*id], self.query(&[*id])?)))
+                    .collect(),
+                None => vec![],
+            };
+            let asked = answered.iter().map(|(ids, _)| ids.len()).sum::<usize>();
+            self.failed = (asked < wanted).then_some(now);
+            for (ids, found) in answered {
+                for job in found {
+                    self.known.insert(job.id, (job, now));
+                }
+                for id in ids {
+                    self.known
+                        .entry(id)
+                        .or_insert_with(|| (SlurmJob::bare(id), now));
+                }
+            }
+        }
+        ids.iter()
+            .map(|id| {
+                let job = match self.known.get(id) {
+                    Some((job, _)) => job.clone(),
+                    None => SlurmJob::bare(*id),
+                };
+                (*id, job)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use super::Slurm;
+
+    /// A stand-in for `squeue` that knows job 1234, fails while a `down`
+    /// file exists, and counts its calls in `calls`.
+    fn stub(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gmon-squeue-{}-{}", name, std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let script = dir.join("squeue");
+        fs::write(
+            &script,
+            r#"#!/bin/sh
+dir=$(dirname "$0")
+echo "$@" >> "$dir/calls"
+[ -e "$dir/down" ] && exit 1
+for id in $(echo "$3" | tr , ' '); do
+    case $id in
+    1234) echo "1234|alice|gpu|1-00:00:00|RUNNING|train | big" ;;
+    esac
+done
+"#,
+        )
+        .unwrap();
+        fs::set_permissions(&script, fs::Permissions::from_mode(0o755)).unwrap();
+        script
+    }
+
+    fn calls(script: &Path) -> usize {
+        fs::read_to_string(script.with_file_name("calls"))
+            .map(|calls| calls.lines().count())
+            .unwrap_or(0)
+    }
+
+    #[test]
+    fn reads_and_caches_squeue_output() {
+        let script = stub("cache");
+        let mut slurm = Slurm::new(&script);
+        let jobs = slurm.jobs(&[1234, 5678]);
+        assert_eq!(jobs[&1234].name.as_deref(), Some("train | big"));
+        assert_eq!(jobs[&1234].user.as_deref(), Some("alice"));
+        assert_eq!(jobs[&1234].partition.as_deref(), Some("gpu"));
+        assert_eq!(jobs[&1234].time_limit.as_deref(), Some("1-00:00:00"));
+        assert_eq!(jobs[&1234].state.as_deref(), Some("RUNNING"));
+        assert_eq!(jobs[&5678].name, None);
+        assert_eq!(calls(&script), 1);
+
+        slurm.jobs(&[1234, 5678]);
+        assert_eq!(calls(&script), 1);
+
+        // Jobs without processes are dropped, and entries expire.
+        slurm.jobs(&[1234]);
+        assert!(!slurm.known.contains_key(&5678));
+        slurm.ttl = Duration::ZERO;
+        slurm.jobs(&[1234]);
+        assert_eq!(calls(&script), 2);
+        fs::remove_dir_all(script.parent().unwrap()).ok();
+    }
+
+    #[test]
+    fn does_not_cache_failed_lookups() {
+        let script = stub("down");
+        let down = script.with_file_name("down");
+        fs::write(&down, "").unwrap();
+        let mut slurm = Slurm::new(&script);
+        assert_eq!(slurm.jobs(&[1234])[&1234].name, None);
+        assert_eq!(calls(&script), 1);
+
+        // Left alone for a while, then asked again.
+        fs::remove_file(&down).unwrap();
+        assert_eq!(slurm.jobs(&[1234])[&1234].name, None);
+        assert_eq!(calls(&script), 1);
+        slurm.retry = Duration::ZERO;
+        assert_eq!(
+            slurm.jobs(&[1234])[&1234].name.as_deref(),
+            Some("train | big")
+        );
+        fs::remove_dir_all(script.parent().unwrap()).ok();
+    }
+}
@@ -0,0 +1,101 @@
+use chrono::{DateTime, Local};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::claim::Claim;
+use crate::collector::Collector;
+use crate::docker::container::Container;
+use crate::kubelet::GpuAllocation;
+use crate::process::GProcess;
+use crate::vendors::error::BackendError;
+use crate::vendors::traits::{GpuInfo, GpuProviderInfo};
+use crate::vendors::types::{
+    ClockInfo, Counters, CudaVersion, MemoryInfo, PowerInfo, ProcessInfo, Value,
+};
+
+/// Whether a device could be queried.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum DeviceStatus {
+    Available,
+    /// The device has fallen off the bus.
+    Lost {
+        reason: String,
+    },
+    /// The device could not be reached for any other reason.
+    Unavailable {
+        reason: String,
+    },
+}
+
+/// State of a single GPU at the time a [`Snapshot`] was collected.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Device {
+    pub index: u32,
+    pub status: DeviceStatus,
+    pub uuid: Option<Value<String>>,
+    pub name: Option<Value<String>>,
+    #[serde(default)]
+    pub pci_bus_id: Option<Value<String>>,
+    pub utilization: Option<Value<u32>>,
+    pub temperature: Option<Value<u32>>,
+    pub power: Option<PowerInfo>,
+    pub memory: Option<MemoryInfo>,
+    pub clocks: Option<ClockInfo>,
+    pub counters: Option<Counters>,
+    pub processes: Vec<ProcessInfo>,
+    /// UUIDs of the device's MIG instances, if MIG is enabled.
+    #[serde(default)]
+    pub mig_uuids: Vec<String>,
+}
+
+impl Device {
+    pub fn is_available(&self) -> bool {
+        self.status == DeviceStatus::Available
+    }
+}
+
+/// Everything gmon knows about the host at one point in time.
+///
+/// A snapshot owns all of its data, so it can be rendered, serialized or
+/// compared after the provider it was collected from has gone away.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Snapshot {
+    pub timestamp: DateTime<Local>,
+    pub host: String,
+    pub driver_version: Option<Value<String>>,
+    pub cuda_version: Option<CudaVersion>,
+    pub devices: Vec<Device>,
+    pub processes: Vec<GProcess>,
+    pub containers: Vec<Container>,
+    /// Claims on any GPU of the host, whether sampled or not.
+    #[serde(default)]
+    pub claims: Vec<Claim>,
+    /// GPUs the kubelet allocated to containers, if asked.
+    #[serde(default)]
+    pub pods: Vec<GpuAllocation>,
+}
+
+impl Snapshot {
+    /// Queries every device of `provider` and attributes the processes
+    /// running on them to users and, with `container_support`, to Docker
+    /// containers.
+    ///
+    /// Use a [`Collector`] to take repeated samples.
+    ///
+    /// ```
+    /// use gmon::vendors::fake::FakeGpuProvider;
+    /// use gmon::Snapshot;
+    ///
+    /// let snapshot = Snapshot::collect(&FakeGpuProvider::with_idle_devices(1), false)?;
+    /// assert_eq!(snapshot.devices.len(), 1);
+    /// # Ok::<(), gmon::BackendError>(())
+    /// ```
+    pub fn collect<'a, P, G>(provider: &'a P, container_support: bool) -> Result<Self, BackendError>
+    where
+        P: GpuProviderInfo<'a, G>,
+        G: GpuInfo<'a> + Send,
+    {
+        Collector::new(provider, container_support).sample()
+    }
+}
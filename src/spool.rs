@@ -0,0 +1,273 @@
+use std::collections::BTreeMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, ErrorKind, Write};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, Local};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum SpoolError {
+    #[error("{0}: {1}")]
+    Io(PathBuf, io::Error),
+    #[error("failed to parse {0}: {1}")]
+    Parse(PathBuf, serde_json::Error),
+    #[error("no job {0}")]
+    NoJob(u64),
+    #[error("a worker is already running on {0}")]
+    WorkerRunning(PathBuf),
+}
+
+/// Where a job is in its life.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum JobState {
+    Queued,
+    Running {
+        /// The shell wrapping the command, which leads its process group; 0
+        /// while it is being started.
+        pid: u32,
+        /// When `pid` started, in clock ticks after boot.
+        #[serde(default)]
+        start_time: Option<u64>,
+        /// The boot `pid` started in. A PID only stands for the job while both
+        /// this and `start_time` match, since it may be reused, e.g. after a
+        /// reboot.
+        #[serde(default)]
+        boot_id: Option<String>,
+        /// Indices of the GPUs the job was given.
+        indices: Vec<u32>,
+        uuids: Vec<String>,
+        started: DateTime<Local>,
+    },
+    /// The command ended; `code` is `None` if it was killed before its exit
+    /// status could be recorded.
+    Exited {
+        code: Option<i32>,
+        indices: Vec<u32>,
+        started: DateTime<Local>,
+        finished: DateTime<Local>,
+    },
+    /// The command could not be started.
+    Failed {
+        reason: String,
+        finished: DateTime<Local>,
+    },
+    Cancelled {
+        finished: DateTime<Local>,
+    },
+}
+
+impl JobState {
+    pub fn is_finished(&self) -> bool {
+        !matches!(self, JobState::Queued | JobState::Running { .. })
+    }
+}
+
+/// A command waiting for, or running on, GPUs.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Job {
+    pub id: u64,
+    pub command: Vec<String>,
+    pub cwd: PathBuf,
+    /// The environment at submission, restored when the job starts.
+    pub env: BTreeMap<String, String>,
+    /// Number of GPUs needed.
+    pub gpus: usize,
+    /// Free memory needed on each GPU, in MiB.
+    pub min_free_memory: u64,
+    /// Highest utilization tolerated on each GPU, in percent.
+    pub max_utilization: u32,
+    pub submitted: DateTime<Local>,
+    #[serde(flatten)]
+    pub state: JobState,
+}
+
+/// Holds a lock on a spool until dropped.
+pub struct SpoolLock {
+    _file: File,
+}
+
+fn lock(path: &Path, wait: bool) -> Result<Option<SpoolLock>, SpoolError> {
+    let file = OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+        .map_err(|e| SpoolError::Io(path.to_path_buf(), e))?;
+    let op = if wait {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_EX | libc::LOCK_NB
+    };
+    if unsafe { libc::flock(file.as_raw_fd(), op) } == 0 {
+        return Ok(Some(SpoolLock { _file: file }));
+    }
+    match io::Error::last_os_error() {
+        e if e.kind() == ErrorKind::WouldBlock => Ok(None),
+        e => Err(SpoolError::Io(path.to_path_buf(), e)),
+    }
+}
+
+/// A directory of queued jobs, their logs and exit statuses.
+///
+/// Every job is a JSON file under `jobs/`, replaced atomically on each
+/// change, so the queue survives restarts of the worker and of the host.
+/// Changes of state happen under [`Spool::lock`].
+///
+/// ```
+/// use gmon::spool::{JobState, Spool};
+///
+/// let dir = std::env::temp_dir().join(format!("gmon-spool-{}", std::process::id()));
+/// let spool = Spool::new(&dir);
+/// let job = spool.submit(vec![String::from("true")], 1, 0, 100)?;
+/// assert_eq!(spool.job(job.id)?.state, JobState::Queued);
+/// # std::fs::remove_dir_all(&dir).ok();
+/// # Ok::<(), gmon::spool::SpoolError>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct Spool {
+    dir: PathBuf,
+}
+
+impl Spool {
+    pub fn new(dir: impl AsRef<Path>) -> Self {
+        Spool {
+            dir: dir.as_ref().to_path_buf(),
+        }
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn jobs_dir(&self) -> PathBuf {
+        self.dir.join("jobs")
+    }
+
+    fn job_path(&self, id: u64) -> PathBuf {
+        self.jobs_dir().join(format!("{}.json", id))
+    }
+
+    /// Output of job `id`, stdout and stderr interleaved.
+    pub fn log_path(&self, id: u64) -> PathBuf {
+        self.dir.join("logs").join(format!("{}.log", id))
+    }
+
+    /// Written by the job's wrapper shell with the command's exit status.
+    pub fn status_path(&self, id: u64) -> PathBuf {
+        self.jobs_dir().join(format!("{}.status", id))
+    }
+
+    fn create_dirs(&self) -> Result<(), SpoolError> {
+        for dir in [self.jobs_dir(), self.dir.join("logs")] {
+            // Jobs carry their submitter's environment; keep them private.
+            fs::DirBuilder::new()
+                .recursive(true)
+                .mode(0o700)
+                .create(&dir)
+                .map_err(|e| SpoolError::Io(dir.clone(), e))?;
+        }
+        Ok(())
+    }
+
+    /// Serializes changes of job state between processes.
+    pub fn lock(&self) -> Result<SpoolLock, SpoolError> {
+        self.create_dirs()?;
+        lock(&self.dir.join("lock"), true).map(|held| held.expect("blocking lock is held"))
+    }
+
+    /// Marks the caller as the only worker of this spool.
+    pub fn lock_worker(&self) -> Result<SpoolLock, SpoolError> {
+        self.create_dirs()?;
+        lock(&self.dir.join("worker.lock"), false)?
+            .ok_or_else(|| SpoolError::WorkerRunning(self.dir.clone()))
+    }
+
+    /// Queues `command` to run in the current directory and environment.
+    pub fn submit(
+        &self,
+        command: Vec<String>,
+        gpus: usize,
+        min_free_memory: u64,
+        max_utilization: u32,
+    ) -> Result<Job, SpoolError> {
+        let _lock = self.lock()?;
+        let cwd = std::env::current_dir().map_err(|e| SpoolError::Io(PathBuf::from("."), e))?;
+        let env = std::env::vars_os()
+            .filter_map(|(key, val)| Some((key.into_string().ok()?, val.into_string().ok()?)))
+            .filter(|(key, _)| key != "CUDA_VISIBLE_DEVICES" && key != "CUDA_DEVICE_ORDER")
+            .collect();
+        let id = self.jobs()?.last().map_or(1, |job| job.id + 1);
+        let job = Job {
+            id,
+            command,
+            cwd,
+            env,
+            gpus,
+            min_free_memory,
+            max_utilization,
+            submitted: Local::now(),
+            state: JobState::Queued,
+        };
+        self.save(&job)?;
+        Ok(job)
+    }
+
+    pub fn job(&self, id: u64) -> Result<Job, SpoolError> {
+        let path = self.job_path(id);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Err(SpoolError::NoJob(id)),
+            Err(e) => return Err(SpoolError::Io(path, e)),
+        };
+        serde_json::from_str(&text).map_err(|e| SpoolError::Parse(path, e))
+    }
+
+    /// All jobs, oldest first.
+    pub fn jobs(&self) -> Result<Vec<Job>, SpoolError> {
+        let dir = self.jobs_dir();
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(SpoolError::Io(dir, e)),
+        };
+        let mut jobs = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().into_string().ok()?;
+                name.strip_suffix(".json")?.parse::<u64>().ok()
+            })
+            .map(|id| self.job(id))
+            .collect::<Result<Vec<_>, _>>()?;
+        jobs.sort_by_key(|job| job.id);
+        Ok(jobs)
+    }
+
+    /// Writes `job`, replacing its previous state in one step.
+    pub fn save(&self, job: &Job) -> Result<(), SpoolError> {
+        self.create_dirs()?;
+        let path = self.job_path(job.id);
+        let tmp = self
+            .jobs_dir()
+            .join(format!(".{}.{}", job.id, std::process::id()));
+        let text = serde_json::to_string_pretty(job).expect("job is always serializable");
+        let res = OpenOptions::new()
+            .create(true)
+            .truncate(true)
+            .write(true)
+            .mode(0o600)
+            .open(&tmp)
+            .and_then(|mut file| file.write_all(text.as_bytes()))
+            .and_then(|_| fs::rename(&tmp, &path));
+        if let Err(e) = res {
+            let _ = fs::remove_file(&tmp);
+            return Err(SpoolError::Io(path, e));
+        }
+        Ok(())
+    }
+}
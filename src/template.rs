@@ -0,0 +1,665 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+use gmon::process::GProcess;
+use gmon::snapshot::{Device, DeviceStatus, Snapshot};
+use owo_colors::{AnsiColors, Style};
+use thiserror::Error;
+
+use crate::display::{color_by_name, memory_color, temperature_color, usage_color, DF};
+
+#[derive(Error, Debug)]
+pub enum TemplateError {
+    #[error("unknown field \"{field}\" in template, available fields: {available}")]
+    UnknownField { field: String, available: String },
+    #[error("invalid format spec \"{0}\", expected [<|>|^][WIDTH][.MAX]")]
+    Spec(String),
+    #[error("unknown colour \"{0}\", expected auto or a colour name")]
+    Colour(String),
+    #[error("unmatched '{0}' in template")]
+    Unmatched(char),
+}
+
+struct Field<T> {
+    name: &'static str,
+    numeric: bool,
+    get: fn(&T, &Snapshot) -> Option<String>,
+    /// Colour picked by `:auto`, usually from the same thresholds as the
+    /// default table.
+    auto: fn(&T) -> Option<AnsiColors>,
+}
+
+fn none<T>(_: &T) -> Option<AnsiColors> {
+    None
+}
+
+fn mem_percent(device: &Device) -> Option<u32> {
+    device
+        .memory
+        .as_ref()
+        .and_then(|m| (m.used.val * 100).checked_div(m.total.val))
+        .map(|p| p as u32)
+}
+
+/// `[[days-]hours:]minutes:seconds`, like the `etime` of ps.
+fn format_elapsed(secs: u64) -> String {
+    let (days, hours, mins) = (secs / 86400, secs / 3600 % 24, secs / 60 % 60);
+    match (days, hours) {
+        (0, 0) => format!("{:02}:{:02}", mins, secs % 60),
+        (0, _) => format!("{:02}:{:02}:{:02}", hours, mins, secs % 60),
+        _ => format!("{}-{:02}:{:02}:{:02}", days, hours, mins, secs % 60),
+    }
+}
+
+static GPU_FIELDS: &[Field<Device>] = &[
+    Field {
+        name: "index",
+        numeric: true,
+        get: |d, _| Some(d.index.to_string()),
+        auto: |_| Some(AnsiColors::Yellow),
+    },
+    Field {
+        name: "name",
+        numeric: false,
+        get: |d, _| d.name.as_ref().map(|v| v.val.clone()),
+        auto: none,
+    },
+    Field {
+        name: "uuid",
+        numeric: false,
+        get: |d, _| d.uuid.as_ref().map(|v| v.val.clone()),
+        auto: none,
+    },
+    Field {
+        name: "status",
+        numeric: false,
+        get: |d, _| {
+            Some(match &d.status {
+                DeviceStatus::Available => String::from("available"),
+                DeviceStatus::Lost { reason } => format!("lost: {}", reason),
+                DeviceStatus::Unavailable { reason } => format!("unavailable: {}", reason),
+            })
+        },
+        auto: |d| (!d.is_available()).then_some(AnsiColors::Red),
+    },
+    Field {
+        name: "temp",
+        numeric: true,
+        get: |d, _| d.temperature.as_ref().map(|v| v.val.to_string()),
+        auto: |d| d.temperature.as_ref().map(|v| temperature_color(v.val)),
+    },
+    Field {
+        name: "util",
+        numeric: true,
+        get: |d, _| d.utilization.as_ref().map(|v| v.val.to_string()),
+        auto: |d| d.utilization.as_ref().map(|v| usage_color(v.val)),
+    },
+    Field {
+        name: "mem.used",
+        numeric: true,
+        get: |d, _| d.memory.as_ref().map(|m| m.used.val.to_string()),
+        auto: |d| mem_percent(d).map(memory_color),
+    },
+    Field {
+        name: "mem.total",
+        numeric: true,
+        get: |d, _| d.memory.as_ref().map(|m| m.total.val.to_string()),
+        auto: none,
+    },
+    Field {
+        name: "mem.free",
+        numeric: true,
+        get: |d, _| {
+            d.memory
+                .as_ref()
+                .map(|m| m.total.val.saturating_sub(m.used.val).to_string())
+        },
+        auto: none,
+    },
+    Field {
+        name: "mem.percent",
+        numeric: true,
+        get: |d, _| mem_percent(d).map(|p| p.to_string()),
+        auto: |d| mem_percent(d).map(memory_color),
+    },
+    Field {
+        name: "power.draw",
+        numeric: true,
+        get: |d, _| d.power.as_ref().map(|p| (p.read.val / 1000).to_string()),
+        auto: none,
+    },
+    Field {
+        name: "power.limit",
+        numeric: true,
+        get: |d, _| d.power.as_ref().map(|p| (p.limit.val / 1000).to_string()),
+        auto: |d| {
+            d.power
+                .as_ref()
+                .filter(|p| p.limit.val != p.limit_default.val)
+                .map(|_| AnsiColors::Blue)
+        },
+    },
+    Field {
+        name: "clocks.gr",
+        numeric: true,
+        get: |d, _| d.clocks.as_ref().map(|c| c.graphics.val.to_string()),
+        auto: none,
+    },
+    Field {
+        name: "clocks.sm",
+        numeric: true,
+        get: |d, _| d.clocks.as_ref().map(|c| c.sm.val.to_string()),
+        auto: none,
+    },
+    Field {
+        name: "clocks.mem",
+        numeric: true,
+        get: |d, _| d.clocks.as_ref().map(|c| c.memory.val.to_string()),
+        auto: none,
+    },
+    Field {
+        name: "procs",
+        numeric: true,
+        get: |d, _| Some(d.processes.len().to_string()),
+        auto: none,
+    },
+    Field {
+        name: "claim",
+        numeric: false,
+        get: |d, s| {
+            let uuid = &d.uuid.as_ref()?.val;
+            let claim = s.claims.iter().find(|claim| &claim.uuid == uuid)?;
+            Some(claim.user.clone())
+        },
+        auto: |_| Some(AnsiColors::Magenta),
+    },
+    Field {
+        name: "pod",
+        numeric: false,
+        get: |d, s| {
+            let uuid = &d.uuid.as_ref()?.val;
+            let pods = s
+                .pods
+                .iter()
+                .filter(|alloc| &alloc.uuid == uuid)
+                .map(|alloc| alloc.pod.to_string())
+                .collect::<Vec<_>>();
+            Some(pods.join(",")).filter(|pods| !pods.is_empty())
+        },
+        auto: |_| Some(AnsiColors::Cyan),
+    },
+];
+
+static PROCESS_FIELDS: &[Field<GProcess>] = &[
+    Field {
+        name: "gpu",
+        numeric: true,
+        get: |p, _| Some(p.gpu_id.to_string()),
+        auto: |_| Some(AnsiColors::Yellow),
+    },
+    Field {
+        name: "pid",
+        numeric: true,
+        get: |p, _| Some(p.pid.to_string()),
+        auto: none,
+    },
+    Field {
+        name: "uid",
+        numeric: true,
+        get: |p, _| Some(p.uid.to_string()),
+        auto: none,
+    },
+    Field {
+        name: "gid",
+        numeric: true,
+        get: |p, _| Some(p.gid.to_string()),
+        auto: none,
+    },
+    Field {
+        name: "user",
+        numeric: false,
+        get: |p, _| Some(p.user_or_uid()),
+        auto: |_| Some(AnsiColors::BrightBlack),
+    },
+    Field {
+        name: "group",
+        numeric: false,
+        get: |p, _| Some(p.group.clone().unwrap_or_else(|| p.gid.to_string())),
+        auto: none,
+    },
+    Field {
+        name: "nspid",
+        numeric: true,
+        get: |p, _| p.container_pid().map(|pid| pid.to_string()),
+        auto: none,
+    },
+    Field {
+        name: "leader",
+        numeric: true,
+        get: |p, _| Some(p.leader.to_string()),
+        auto: none,
+    },
+    Field {
+        name: "command",
+        numeric: false,
+        get: |p, _| Some(p.name.clone()).filter(|name| !name.is_empty()),
+        auto: none,
+    },
+    Field {
+        name: "cmdline",
+        numeric: false,
+        get: |p, _| Some(p.cmdline.join(" ")).filter(|cmd| !cmd.is_empty()),
+        auto: none,
+    },
+    Field {
+        name: "mem",
+        numeric: true,
+        get: |p, _| p.gpu_info.used_memory.as_ref().map(|m| m.val.to_string()),
+        auto: none,
+    },
+    Field {
+        name: "cpu",
+        numeric: true,
+        get: |p, _| p.cpu_percent.map(|cpu| format!("{:.0}", cpu)),
+        auto: none,
+    },
+    Field {
+        name: "rss",
+        numeric: true,
+        get: |p, _| p.rss.map(|rss| (rss / 1024 / 1024).to_string()),
+        auto: none,
+    },
+    Field {
+        name: "elapsed",
+        numeric: false,
+        get: |p, _| p.elapsed.map(format_elapsed),
+        auto: none,
+    },
+    Field {
+        name: "container",
+        numeric: false,
+        get: |p, s| p.container(&s.containers).map(|ctr| ctr.name.clone()),
+        auto: |_| Some(AnsiColors::Cyan),
+    },
+    Field {
+        name: "job",
+        numeric: true,
+        get: |p, _| p.slurm_job.as_ref().map(|job| job.id.to_string()),
+        auto: |_| Some(AnsiColors::Magenta),
+    },
+    Field {
+        name: "job.name",
+        numeric: false,
+        get: |p, _| p.slurm_job.as_ref().and_then(|job| job.name.clone()),
+        auto: none,
+    },
+    Field {
+        name: "job.user",
+        numeric: false,
+        get: |p, _| p.slurm_job.as_ref().and_then(|job| job.user.clone()),
+        auto: none,
+    },
+    Field {
+        name: "job.partition",
+        numeric: false,
+        get: |p, _| p.slurm_job.as_ref().and_then(|job| job.partition.clone()),
+        auto: none,
+    },
+    Field {
+        name: "job.time_limit",
+        numeric: false,
+        get: |p, _| p.slurm_job.as_ref().and_then(|job| job.time_limit.clone()),
+        auto: none,
+    },
+    Field {
+        name: "pod",
+        numeric: false,
+        get: |p, _| p.pod.as_ref().map(|pod| pod.to_string()),
+        auto: |_| Some(AnsiColors::Cyan),
+    },
+    Field {
+        name: "owner",
+        numeric: false,
+        get: |p, _| p.owner.as_ref().map(|owner| owner.to_string()),
+        auto: |_| Some(AnsiColors::Cyan),
+    },
+];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    Right,
+    Center,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Colour {
+    Auto,
+    Fixed(AnsiColors),
+}
+
+#[derive(Debug, Clone)]
+enum Piece {
+    Text(String),
+    Field {
+        index: usize,
+        align: Option<Align>,
+        width: usize,
+        max: Option<usize>,
+        colour: Option<Colour>,
+    },
+}
+
+fn parse_colour(name: &str) -> Result<Colour, TemplateError> {
+    match name {
+        "auto" => Ok(Colour::Auto),
+        _ => color_by_name(name)
+            .map(Colour::Fixed)
+            .ok_or_else(|| TemplateError::Colour(name.to_string())),
+    }
+}
+
+fn parse_field<T>(fields: &[Field<T>], inner: &str) -> Result<Piece, TemplateError> {
+    let mut parts = inner.splitn(3, ':');
+    let name = parts.next().unwrap_or_default().trim();
+    let index =
+        fields
+            .iter()
+            .position(|f| f.name == name)
+            .ok_or_else(|| TemplateError::UnknownField {
+                field: name.to_string(),
+                available: fields.iter().map(|f| f.name).collect::<Vec<_>>().join(", "),
+            })?;
+    let spec = parts.next().unwrap_or_default();
+    let colour = parts.next().map(parse_colour).transpose()?;
+    let (align, rest) = match spec.chars().next() {
+        Some('<') => (Some(Align::Left), &spec[1..]),
+        Some('>') => (Some(Align::Right), &spec[1..]),
+        Some('^') => (Some(Align::Center), &spec[1..]),
+        _ => (None, spec),
+    };
+    let (width, max) = match rest.split_once('.') {
+        Some((width, max)) => (width, Some(max)),
+        None => (rest, None),
+    };
+    let bad_spec = || TemplateError::Spec(spec.to_string());
+    let width = if width.is_empty() {
+        0
+    } else {
+        width.parse().map_err(|_| bad_spec())?
+    };
+    let max = max
+        .map(|max| max.parse::<usize>().map_err(|_| bad_spec()))
+        .transpose()?;
+    Ok(Piece::Field {
+        index,
+        align,
+        width,
+        max,
+        colour,
+    })
+}
+
+fn parse<T>(fields: &[Field<T>], template: &str) -> Result<Vec<Piece>, TemplateError> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                text.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                text.push('}');
+            }
+            '{' => {
+                let mut inner = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => inner.push(c),
+                        None => return Err(TemplateError::Unmatched('{')),
+                    }
+                }
+                if !text.is_empty() {
+                    pieces.push(Piece::Text(std::mem::take(&mut text)));
+                }
+                pieces.push(parse_field(fields, &inner)?);
+            }
+            '}' => return Err(TemplateError::Unmatched('}')),
+            c => text.push(c),
+        }
+    }
+    if !text.is_empty() {
+        pieces.push(Piece::Text(text));
+    }
+    Ok(pieces)
+}
+
+fn render<T>(
+    df: &DF,
+    fields: &[Field<T>],
+    pieces: &[Piece],
+    item: &T,
+    snapshot: &Snapshot,
+) -> String {
+    let mut out = String::new();
+    for piece in pieces {
+        let (index, align, width, max, colour) = match piece {
+            Piece::Text(text) => {
+                out.push_str(text);
+                continue;
+            }
+            Piece::Field {
+                index,
+                align,
+                width,
+                max,
+                colour,
+            } => (*index, *align, *width, *max, *colour),
+        };
+        let field = &fields[index];
+        let mut val = (field.get)(item, snapshot).unwrap_or_else(|| String::from("N/A"));
+        if let Some(max) = max {
+            val = val.chars().take(max).collect();
+        }
+        let gap = width.saturating_sub(val.chars().count());
+        let align = align.unwrap_or(if field.numeric {
+            Align::Right
+        } else {
+            Align::Left
+        });
+        let (left, right) = match align {
+            Align::Left => (0, gap),
+            Align::Right => (gap, 0),
+            Align::Center => (gap / 2, gap - gap / 2),
+        };
+        let colour = match colour {
+            Some(Colour::Auto) => (field.auto)(item),
+            Some(Colour::Fixed(colour)) => Some(colour),
+            None => None,
+        };
+        let _ = write!(out, "{:left$}", "", left = left);
+        match colour {
+            Some(colour) => {
+                let _ = write!(out, "{}", df.try_style(&val, Style::new().color(colour)));
+            }
+            None => out.push_str(&val),
+        }
+        let _ = write!(out, "{:right$}", "", right = right);
+    }
+    out
+}
+
+/// The default table's GPU line, used when only a process template is given.
+pub const DEFAULT_GPU_FORMAT: &str =
+    "{index::auto} {name} {temp:>3:auto}°C {power.draw:>3}W {util:>3}% {mem.used}/{mem.total}MiB";
+
+/// A per-GPU line such as `{index} {name:20} {temp:>3:auto}°C {util:>3}%`.
+///
+/// Each field is `{NAME}`, `{NAME:SPEC}` or `{NAME:SPEC:COLOUR}`, where SPEC
+/// is an optional alignment (`<`, `>`, `^`), a minimum width and `.MAX` to
+/// truncate, and COLOUR is a colour name or `auto` for the same thresholds as
+/// the default table. `{{` and `}}` are literal braces.
+#[derive(Debug, Clone)]
+pub struct GpuTemplate(Vec<Piece>, String);
+
+impl GpuTemplate {
+    pub fn source(&self) -> &str {
+        &self.1
+    }
+}
+
+impl FromStr for GpuTemplate {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(GPU_FIELDS, s).map(|pieces| GpuTemplate(pieces, s.to_string()))
+    }
+}
+
+/// A per-process line, printed under the line of the GPU it runs on, such
+/// as `  {pid:>7} {command:15} {mem:>6}MiB {container}`.
+#[derive(Debug, Clone)]
+pub struct ProcessTemplate(Vec<Piece>, String);
+
+impl ProcessTemplate {
+    pub fn source(&self) -> &str {
+        &self.1
+    }
+}
+
+impl FromStr for ProcessTemplate {
+    type Err = TemplateError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse(PROCESS_FIELDS, s).map(|pieces| ProcessTemplate(pieces, s.to_string()))
+    }
+}
+
+pub fn render_templates(
+    snapshot: &Snapshot,
+    gpu: &GpuTemplate,
+    process: Option<&ProcessTemplate>,
+) -> String {
+    let df = DF::new();
+    let mut out = String::new();
+    for device in snapshot.devices.iter() {
+        out.push_str(&render(&df, GPU_FIELDS, &gpu.0, device, snapshot));
+        out.push('\n');
+        let process = match process {
+            Some(process) => process,
+            None => continue,
+        };
+        for proc in snapshot
+            .processes
+            .iter()
+            .filter(|p| p.gpu_id == device.index)
+        {
+            out.push_str(&render(&df, PROCESS_FIELDS, &process.0, proc, snapshot));
+            out.push('\n');
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use gmon::vendors::fake::FakeGpuProvider;
+    use gmon::Snapshot;
+
+    use super::*;
+
+    fn render_gpu(template: &str) -> String {
+        let snapshot = Snapshot::collect(&FakeGpuProvider::with_idle_devices(1), false).unwrap();
+        let gpu = template.parse::<GpuTemplate>().unwrap();
+        render_templates(&snapshot, &gpu, None)
+    }
+
+    #[test]
+    fn parses_fields_and_specs() {
+        let pieces = parse(GPU_FIELDS, "GPU {index:>3} {name:^12.4:red}").unwrap();
+        assert_eq!(pieces.len(), 4);
+        assert!(matches!(&pieces[0], Piece::Text(text) if text == "GPU "));
+        assert!(matches!(
+            pieces[1],
+            Piece::Field {
+                align: Some(Align::Right),
+                width: 3,
+                max: None,
+                colour: None,
+                ..
+            }
+        ));
+        assert!(matches!(
+            pieces[3],
+            Piece::Field {
+                align: Some(Align::Center),
+                width: 12,
+                max: Some(4),
+                colour: Some(Colour::Fixed(AnsiColors::Red)),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn renders_alignment_and_truncation() {
+        assert_eq!(render_gpu("[{index:3}] [{name:<6.4}]"), "[  0] [Fake  ]\n");
+        assert_eq!(render_gpu("[{name:^10}]"), "[ Fake GPU ]\n");
+    }
+
+    #[test]
+    fn doubled_braces_are_literal() {
+        assert_eq!(render_gpu("{{index}} = {index}"), "{index} = 0\n");
+        assert!(matches!(
+            parse(GPU_FIELDS, "{index"),
+            Err(TemplateError::Unmatched('{'))
+        ));
+        assert!(matches!(
+            parse(GPU_FIELDS, "index}"),
+            Err(TemplateError::Unmatched('}'))
+        ));
+    }
+
+    #[test]
+    fn unknown_fields_list_the_valid_names() {
+        let e = "{pid}".parse::<GpuTemplate>().unwrap_err();
+        let msg = e.to_string();
+        assert!(msg.starts_with("unknown field \"pid\""), "{}", msg);
+        for field in GPU_FIELDS {
+            assert!(
+                msg.contains(field.name),
+                "{} missing from {}",
+                field.name,
+                msg
+            );
+        }
+        assert!("{pid}".parse::<ProcessTemplate>().is_ok());
+    }
+
+    #[test]
+    fn rejects_bad_specs_and_colours() {
+        assert!(matches!(
+            parse(GPU_FIELDS, "{index:x}"),
+            Err(TemplateError::Spec(_))
+        ));
+        assert!(matches!(
+            parse(GPU_FIELDS, "{index:>3.y}"),
+            Err(TemplateError::Spec(_))
+        ));
+        assert!(matches!(
+            parse(GPU_FIELDS, "{index::mauve}"),
+            Err(TemplateError::Colour(_))
+        ));
+    }
+
+    #[test]
+    fn formats_elapsed_like_ps() {
+        assert_eq!(format_elapsed(65), "01:05");
+        assert_eq!(format_elapsed(3 * 3600 + 65), "03:01:05");
+        assert_eq!(format_elapsed(2 * 86400 + 3 * 3600 + 65), "2-03:01:05");
+    }
+}
@@ -0,0 +1,503 @@
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::io;
+use std::time::{Duration, Instant};
+
+use gmon::collector::Collector;
+use gmon::process::{group_processes, GProcess, ProcessGroup};
+use gmon::snapshot::Snapshot;
+use gmon::vendors::error::BackendError;
+use gmon::vendors::traits::{GpuInfo, GpuProviderInfo};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::widgets::TableState;
+use thiserror::Error;
+
+use crate::display::DF;
+
+mod ui;
+
+/// Samples kept per device, five minutes at the default interval.
+const HISTORY_LEN: usize = 300;
+
+#[derive(Error, Debug)]
+pub enum TuiError {
+    #[error("terminal error: {0}")]
+    Io(#[from] io::Error),
+    #[error("{0}")]
+    Backend(#[from] BackendError),
+}
+
+#[derive(Debug, Default)]
+struct History {
+    utilization: VecDeque<u64>,
+    memory: VecDeque<u64>,
+    power: VecDeque<u64>,
+}
+
+fn push(buf: &mut VecDeque<u64>, val: u64) {
+    if buf.len() == HISTORY_LEN {
+        buf.pop_front();
+    }
+    buf.push_back(val);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SortColumn {
+    User,
+    Container,
+    Owner,
+    Job,
+    Pid,
+    Gpu,
+    Memory,
+    Command,
+}
+
+impl SortColumn {
+    const ALL: [SortColumn; 8] = [
+        SortColumn::User,
+        SortColumn::Container,
+        SortColumn::Owner,
+        SortColumn::Job,
+        SortColumn::Pid,
+        SortColumn::Gpu,
+        SortColumn::Memory,
+        SortColumn::Command,
+    ];
+
+    fn title(&self) -> &'static str {
+        match self {
+            SortColumn::User => "USER",
+            SortColumn::Container => "CONTAINER",
+            SortColumn::Owner => "OWNER",
+            SortColumn::Job => "JOB",
+            SortColumn::Pid => "PID",
+            SortColumn::Gpu => "GPU",
+            SortColumn::Memory => "GPU MEM",
+            SortColumn::Command => "COMMAND",
+        }
+    }
+
+    fn shift(&self, step: isize) -> Self {
+        let pos = Self::ALL.iter().position(|c| c == self).unwrap_or(0) as isize;
+        let len = Self::ALL.len() as isize;
+        Self::ALL[((pos + step).rem_euclid(len)) as usize]
+    }
+}
+
+enum Mode {
+    Normal,
+    Filter(String),
+    Confirm {
+        pids: Vec<u32>,
+        name: String,
+        signal: i32,
+    },
+}
+
+/// What a row of the process table stands for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RowKind {
+    Process,
+    /// A process group, and whether its members are listed below it.
+    Group {
+        expanded: bool,
+    },
+    /// A process listed under its group.
+    Member,
+}
+
+/// One row of the process table.
+struct ProcRow {
+    kind: RowKind,
+    user: String,
+    container: String,
+    owner: String,
+    /// Slurm job.
+    job: Option<u64>,
+    /// The process or, for a group, its leader.
+    pid: u32,
+    /// `pid` inside its container.
+    container_pid: Option<u32>,
+    gpus: Vec<u32>,
+    memory: Option<u64>,
+    command: String,
+    /// Processes signalled from this row.
+    pids: Vec<u32>,
+}
+
+impl ProcRow {
+    fn new(proc: &GProcess, snapshot: &Snapshot) -> Self {
+        ProcRow {
+            kind: RowKind::Process,
+            user: proc.user_or_uid(),
+            container: container_name(proc, snapshot),
+            owner: proc
+                .owner
+                .as_ref()
+                .map(|owner| owner.to_string())
+                .unwrap_or_default(),
+            job: proc.slurm_job.as_ref().map(|job| job.id),
+            pid: proc.pid,
+            container_pid: proc.container_pid(),
+            gpus: vec![proc.gpu_id],
+            memory: proc.gpu_info.used_memory.as_ref().map(|mem| mem.val),
+            command: proc.name.clone(),
+            pids: vec![proc.pid],
+        }
+    }
+
+    fn group(group: &ProcessGroup, snapshot: &Snapshot, expanded: bool) -> Self {
+        let mut pids = group
+            .processes
+            .iter()
+            .map(|proc| proc.pid)
+            .collect::<Vec<_>>();
+        pids.sort_unstable();
+        pids.dedup();
+        ProcRow {
+            kind: RowKind::Group { expanded },
+            user: group.user.clone().unwrap_or_else(|| group.uid.to_string()),
+            container: container_name(&group.processes[0], snapshot),
+            owner: group.processes[0]
+                .owner
+                .as_ref()
+                .map(|owner| owner.to_string())
+                .unwrap_or_default(),
+            job: group.processes[0].slurm_job.as_ref().map(|job| job.id),
+            pid: group.leader,
+            container_pid: None,
+            gpus: group.gpus.clone(),
+            memory: Some(group.used_memory),
+            command: format!("{} ({} workers)", group.name, group.workers()),
+            pids,
+        }
+    }
+}
+
+/// The Docker container or else the Kubernetes container of `proc`.
+fn container_name(proc: &GProcess, snapshot: &Snapshot) -> String {
+    match (proc.container(&snapshot.containers), &proc.pod) {
+        (Some(ctr), _) => ctr.name.clone(),
+        (None, Some(pod)) => pod.to_string(),
+        (None, None) => String::new(),
+    }
+}
+
+/// GPU indices with consecutive runs shortened, e.g. `0-3,6`.
+fn gpu_list(gpus: &[u32]) -> String {
+    let mut runs: Vec<(u32, u32)> = vec![];
+    for &gpu in gpus {
+        match runs.last_mut() {
+            Some((_, end)) if *end + 1 == gpu => *end = gpu,
+            _ => runs.push((gpu, gpu)),
+        }
+    }
+    runs.iter()
+        .map(|&(start, end)| match end - start {
+            0 => start.to_string(),
+            _ => format!("{}-{}", start, end),
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+struct App {
+    snapshot: Snapshot,
+    history: HashMap<u32, History>,
+    sort: SortColumn,
+    sort_desc: bool,
+    user_filter: Option<String>,
+    /// Whether processes are grouped by leader.
+    grouped: bool,
+    /// Leaders of the groups whose members are listed.
+    expanded: BTreeSet<u32>,
+    mode: Mode,
+    table: TableState,
+    status: Option<String>,
+    color: bool,
+    quit: bool,
+}
+
+impl App {
+    fn new(snapshot: Snapshot) -> Self {
+        let mut app = App {
+            snapshot,
+            history: HashMap::new(),
+            sort: SortColumn::Memory,
+            sort_desc: true,
+            user_filter: None,
+            grouped: false,
+            expanded: BTreeSet::new(),
+            mode: Mode::Normal,
+            table: TableState::default(),
+            status: None,
+            color: DF::new().supports_color(),
+            quit: false,
+        };
+        app.record();
+        app
+    }
+
+    fn update(&mut self, snapshot: Snapshot) {
+        self.snapshot = snapshot;
+        self.record();
+        let len = self.rows().len();
+        if let Some(selected) = self.table.selected() {
+            if selected >= len {
+                self.table.select(len.checked_sub(1));
+            }
+        }
+    }
+
+    fn record(&mut self) {
+        for device in self.snapshot.devices.iter() {
+            let history = self.history.entry(device.index).or_default();
+            push(
+                &mut history.utilization,
+                device.utilization.as_ref().map_or(0, |u| u.val as u64),
+            );
+            push(
+                &mut history.memory,
+                device.memory.as_ref().map_or(0, |m| {
+                    (m.used.val * 100).checked_div(m.total.val).unwrap_or(0)
+                }),
+            );
+            push(
+                &mut history.power,
+                device
+                    .power
+                    .as_ref()
+                    .map_or(0, |p| p.read.val as u64 / 1000),
+            );
+        }
+    }
+
+    fn sort(&self, rows: &mut [ProcRow]) {
+        rows.sort_by(|a, b| {
+            let ord = match self.sort {
+                SortColumn::User => a.user.cmp(&b.user),
+                SortColumn::Container => a.container.cmp(&b.container),
+                SortColumn::Owner => a.owner.cmp(&b.owner),
+                SortColumn::Job => a.job.cmp(&b.job),
+                SortColumn::Pid => a.pid.cmp(&b.pid),
+                SortColumn::Gpu => a.gpus.cmp(&b.gpus),
+                SortColumn::Memory => a.memory.cmp(&b.memory),
+                SortColumn::Command => a.command.cmp(&b.command),
+            };
+            if self.sort_desc {
+                ord.reverse()
+            } else {
+                ord
+            }
+        });
+    }
+
+    fn rows(&self) -> Vec<ProcRow> {
+        let procs = self
+            .snapshot
+            .processes
+            .iter()
+            .filter(|proc| match &self.user_filter {
+                Some(user) => &proc.user_or_uid() == user,
+                None => true,
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+        if !self.grouped {
+            let mut rows = procs
+                .iter()
+                .map(|proc| ProcRow::new(proc, &self.snapshot))
+                .collect::<Vec<_>>();
+            self.sort(&mut rows);
+            return rows;
+        }
+        let groups = group_processes(&procs);
+        let mut rows = groups
+            .iter()
+            .map(|group| {
+                let expanded = self.expanded.contains(&group.leader);
+                ProcRow::group(group, &self.snapshot, expanded)
+            })
+            .collect::<Vec<_>>();
+        self.sort(&mut rows);
+        let mut listed = Vec::with_capacity(rows.len());
+        for row in rows {
+            let expanded = row.kind == RowKind::Group { expanded: true };
+            let leader = row.pid;
+            listed.push(row);
+            if !expanded {
+                continue;
+            }
+            let group = groups
+                .iter()
+                .find(|group| group.leader == leader)
+                .expect("row was made from a group");
+            let mut members = group
+                .processes
+                .iter()
+                .map(|proc| ProcRow {
+                    kind: RowKind::Member,
+                    ..ProcRow::new(proc, &self.snapshot)
+                })
+                .collect::<Vec<_>>();
+            self.sort(&mut members);
+            listed.extend(members);
+        }
+        listed
+    }
+
+    /// Lists or hides the members of the selected group.
+    fn toggle_expanded(&mut self) {
+        let rows = self.rows();
+        if let Some(row) = self.table.selected().and_then(|i| rows.get(i)) {
+            if let RowKind::Group { .. } = row.kind {
+                if !self.expanded.remove(&row.pid) {
+                    self.expanded.insert(row.pid);
+                }
+            }
+        }
+    }
+
+    fn select(&mut self, step: isize) {
+        let len = self.rows().len();
+        if len == 0 {
+            self.table.select(None);
+            return;
+        }
+        let cur = self.table.selected().map_or(-1, |i| i as isize);
+        let next = (cur + step).clamp(0, len as isize - 1);
+        self.table.select(Some(next as usize));
+    }
+
+    fn confirm_signal(&mut self, signal: i32) {
+        let rows = self.rows();
+        if let Some(row) = self.table.selected().and_then(|i| rows.get(i)) {
+            self.mode = Mode::Confirm {
+                pids: row.pids.clone(),
+                name: row.command.clone(),
+                signal,
+            };
+        }
+    }
+
+    fn on_key(&mut self, key: KeyEvent) {
+        if key.modifiers.contains(KeyModifiers::CONTROL) && key.code == KeyCode::Char('c') {
+            self.quit = true;
+            return;
+        }
+        match &mut self.mode {
+            Mode::Normal => match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => self.quit = true,
+                KeyCode::Up => self.select(-1),
+                KeyCode::Down => self.select(1),
+                KeyCode::PageUp => self.select(-10),
+                KeyCode::PageDown => self.select(10),
+                KeyCode::Home => self.select(isize::MIN / 2),
+                KeyCode::End => self.select(isize::MAX / 2),
+                KeyCode::Left => self.sort = self.sort.shift(-1),
+                KeyCode::Right => self.sort = self.sort.shift(1),
+                KeyCode::Char('r') => self.sort_desc = !self.sort_desc,
+                KeyCode::Char('u') | KeyCode::Char('/') => {
+                    self.mode = Mode::Filter(self.user_filter.clone().unwrap_or_default())
+                }
+                KeyCode::Char('g') => {
+                    self.grouped = !self.grouped;
+                    self.table.select(None);
+                }
+                KeyCode::Enter | KeyCode::Char(' ') => self.toggle_expanded(),
+                KeyCode::Char('k') => self.confirm_signal(libc::SIGTERM),
+                KeyCode::Char('K') => self.confirm_signal(libc::SIGKILL),
+                _ => {}
+            },
+            Mode::Filter(input) => match key.code {
+                KeyCode::Enter => {
+                    let input = input.trim().to_string();
+                    self.user_filter = if input.is_empty() { None } else { Some(input) };
+                    self.mode = Mode::Normal;
+                    self.table.select(None);
+                }
+                KeyCode::Esc => self.mode = Mode::Normal,
+                KeyCode::Backspace => {
+                    input.pop();
+                }
+                KeyCode::Char(c) => input.push(c),
+                _ => {}
+            },
+            Mode::Confirm { pids, signal, .. } => {
+                if key.code == KeyCode::Char('y') || key.code == KeyCode::Char('Y') {
+                    // The dialog may have been open for several samples; a
+                    // process gone since then may have passed its PID on.
+                    let snapshot = &self.snapshot;
+                    pids.retain(|pid| snapshot.processes.iter().any(|proc| proc.pid == *pid));
+                    let failed = pids
+                        .iter()
+                        .filter_map(|pid| send_signal(*pid, *signal).err().map(|e| (pid, e)))
+                        .collect::<Vec<_>>();
+                    let pid_list = pids
+                        .iter()
+                        .map(|pid| pid.to_string())
+                        .collect::<Vec<_>>()
+                        .join(",");
+                    self.status = Some(match failed.first() {
+                        None if pids.is_empty() => String::from("the processes have exited"),
+                        None => format!("sent {} to {}", signal_name(*signal), pid_list),
+                        Some((pid, e)) => format!("failed to signal {}: {}", pid, e),
+                    });
+                }
+                self.mode = Mode::Normal;
+            }
+        }
+    }
+}
+
+fn signal_name(signal: i32) -> &'static str {
+    match signal {
+        libc::SIGTERM => "SIGTERM",
+        libc::SIGKILL => "SIGKILL",
+        _ => "signal",
+    }
+}
+
+fn send_signal(pid: u32, signal: i32) -> io::Result<()> {
+    // kill(0, ...) would signal gmon's own process group.
+    let pid = i32::try_from(pid)
+        .ok()
+        .filter(|pid| *pid > 0)
+        .ok_or_else(|| io::Error::from(io::ErrorKind::InvalidInput))?;
+    if unsafe { libc::kill(pid, signal) } == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error())
+    }
+}
+
+/// Runs the full-screen interface until the user quits.
+pub fn run<'a, P, G>(collector: &mut Collector<'a, P>, interval: Duration) -> Result<(), TuiError>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    let mut app = App::new(collector.sample()?);
+    let mut terminal = ratatui::try_init()?;
+    let res = (|| -> Result<(), TuiError> {
+        let mut next_sample = Instant::now() + interval;
+        while !app.quit {
+            terminal.draw(|frame| ui::draw(frame, &mut app))?;
+            let timeout = next_sample.saturating_duration_since(Instant::now());
+            if event::poll(timeout)? {
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        app.on_key(key);
+                    }
+                }
+            }
+            if Instant::now() >= next_sample {
+                app.update(collector.sample()?);
+                next_sample = Instant::now() + interval;
+            }
+        }
+        Ok(())
+    })();
+    ratatui::restore();
+    res
+}
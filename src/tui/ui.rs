@@ -0,0 +1,300 @@
+use std::collections::VecDeque;
+
+use gmon::snapshot::{Device, DeviceStatus};
+use owo_colors::AnsiColors;
+use ratatui::layout::{Constraint, Flex, Layout, Rect};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, Borders, Cell, Clear, Paragraph, Row, Sparkline, Table};
+use ratatui::Frame;
+
+use super::{gpu_list, signal_name, App, Mode, RowKind, SortColumn};
+use crate::display::{temperature_color, usage_color};
+
+const PANEL_HEIGHT: u16 = 5;
+const PANEL_HEIGHT_COMPACT: u16 = 3;
+
+fn color(app: &App, ansi: AnsiColors) -> Color {
+    if !app.color {
+        return Color::Reset;
+    }
+    match ansi {
+        AnsiColors::Black => Color::Black,
+        AnsiColors::Red => Color::Red,
+        AnsiColors::Green => Color::Green,
+        AnsiColors::Yellow => Color::Yellow,
+        AnsiColors::Blue => Color::Blue,
+        AnsiColors::Magenta => Color::Magenta,
+        AnsiColors::Cyan => Color::Cyan,
+        AnsiColors::White => Color::Gray,
+        AnsiColors::BrightBlack => Color::DarkGray,
+        AnsiColors::BrightRed => Color::LightRed,
+        AnsiColors::BrightGreen => Color::LightGreen,
+        AnsiColors::BrightYellow => Color::LightYellow,
+        AnsiColors::BrightBlue => Color::LightBlue,
+        AnsiColors::BrightMagenta => Color::LightMagenta,
+        AnsiColors::BrightCyan => Color::LightCyan,
+        AnsiColors::BrightWhite => Color::White,
+        _ => Color::Reset,
+    }
+}
+
+pub(super) fn draw(frame: &mut Frame, app: &mut App) {
+    let cols: u16 = if frame.area().width >= 120 { 2 } else { 1 };
+    let n = app.snapshot.devices.len() as u16;
+    let grid_rows = n.div_ceil(cols);
+    let panel_height = if grid_rows * PANEL_HEIGHT <= frame.area().height * 2 / 3 {
+        PANEL_HEIGHT
+    } else {
+        PANEL_HEIGHT_COMPACT
+    };
+    let [header, gpus, procs, footer] = Layout::vertical([
+        Constraint::Length(1),
+        Constraint::Length(grid_rows * panel_height),
+        Constraint::Min(3),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    draw_header(frame, app, header);
+    let rows =
+        Layout::vertical((0..grid_rows).map(|_| Constraint::Length(panel_height))).split(gpus);
+    for (i, device) in app.snapshot.devices.iter().enumerate() {
+        let row = rows[i / cols as usize];
+        let cells =
+            Layout::horizontal((0..cols).map(|_| Constraint::Ratio(1, cols as u32))).split(row);
+        draw_device(frame, app, device, cells[i % cols as usize]);
+    }
+    draw_processes(frame, app, procs);
+    draw_footer(frame, app, footer);
+    if let Mode::Confirm { pids, name, signal } = &app.mode {
+        let target = match pids.as_slice() {
+            [pid] => pid.to_string(),
+            _ => format!("{} processes", pids.len()),
+        };
+        let text = format!(
+            "Send {} to {} ({})? [y/N]",
+            signal_name(*signal),
+            target,
+            name
+        );
+        let [area] = Layout::horizontal([Constraint::Length(text.len() as u16 + 4)])
+            .flex(Flex::Center)
+            .areas(frame.area());
+        let [area] = Layout::vertical([Constraint::Length(3)])
+            .flex(Flex::Center)
+            .areas(area);
+        frame.render_widget(Clear, area);
+        frame.render_widget(
+            Paragraph::new(text).block(Block::default().borders(Borders::ALL).title("confirm")),
+            area,
+        );
+    }
+}
+
+fn draw_header(frame: &mut Frame, app: &App, area: Rect) {
+    let snapshot = &app.snapshot;
+    let driver = snapshot
+        .driver_version
+        .as_ref()
+        .map_or("N/A".to_string(), |v| v.val.clone());
+    let cuda = snapshot
+        .cuda_version
+        .as_ref()
+        .map_or("N/A".to_string(), |v| {
+            format!("{}.{}", v.major.val, v.minor.val)
+        });
+    let line = Line::from(vec![
+        Span::raw(snapshot.timestamp.format("%Y/%m/%d %H:%M:%S").to_string()),
+        Span::raw(format!("  {}  {} CUDA:", snapshot.host, driver)),
+        Span::styled(cuda, Style::default().fg(color(app, AnsiColors::Yellow))),
+    ]);
+    frame.render_widget(Paragraph::new(line), area);
+}
+
+fn tail(buf: &VecDeque<u64>, width: u16) -> Vec<u64> {
+    let skip = buf.len().saturating_sub(width as usize);
+    buf.iter().skip(skip).copied().collect()
+}
+
+fn draw_device(frame: &mut Frame, app: &App, device: &Device, area: Rect) {
+    let mut title = vec![Span::styled(
+        format!("{} ", device.index),
+        Style::default().fg(color(app, AnsiColors::Yellow)),
+    )];
+    title.push(Span::raw(
+        device
+            .name
+            .as_ref()
+            .map_or("N/A".to_string(), |n| n.val.clone()),
+    ));
+    if let Some(temp) = &device.temperature {
+        title.push(Span::styled(
+            format!(" {}°C", temp.val),
+            Style::default().fg(color(app, temperature_color(temp.val))),
+        ));
+    }
+    if let Some(power) = &device.power {
+        title.push(Span::raw(format!(
+            " {}/{}W",
+            power.read.val / 1000,
+            power.limit.val / 1000
+        )));
+    }
+    if let Some(mem) = &device.memory {
+        title.push(Span::raw(format!(" {}/{}MiB", mem.used.val, mem.total.val)));
+    }
+    let block = Block::default()
+        .borders(Borders::ALL)
+        .title(Line::from(title));
+    let inner = block.inner(area);
+    frame.render_widget(block, area);
+
+    match &device.status {
+        DeviceStatus::Available => {}
+        DeviceStatus::Lost { reason } | DeviceStatus::Unavailable { reason } => {
+            frame.render_widget(
+                Paragraph::new(reason.as_str())
+                    .style(Style::default().fg(color(app, AnsiColors::Red))),
+                inner,
+            );
+            return;
+        }
+    }
+    let history = match app.history.get(&device.index) {
+        Some(history) => history,
+        None => return,
+    };
+    let power_max = device
+        .power
+        .as_ref()
+        .map_or(1, |p| (p.limit.val as u64 / 1000).max(1));
+    let util = device.utilization.as_ref().map_or(0, |u| u.val);
+    let lines: Vec<(&str, &VecDeque<u64>, u64, Color)> = vec![
+        (
+            "util",
+            &history.utilization,
+            100,
+            color(app, usage_color(util)),
+        ),
+        ("mem", &history.memory, 100, color(app, AnsiColors::Cyan)),
+        (
+            "pow",
+            &history.power,
+            power_max,
+            color(app, AnsiColors::Magenta),
+        ),
+    ];
+    let rows = Layout::vertical((0..inner.height).map(|_| Constraint::Length(1))).split(inner);
+    for ((label, buf, max, fg), row) in lines.into_iter().zip(rows.iter()) {
+        draw_sparkline(frame, label, buf, max, fg, *row);
+    }
+}
+
+fn draw_sparkline(
+    frame: &mut Frame,
+    label: &str,
+    buf: &VecDeque<u64>,
+    max: u64,
+    fg: Color,
+    area: Rect,
+) {
+    let [label_area, spark_area, value_area] = Layout::horizontal([
+        Constraint::Length(5),
+        Constraint::Min(1),
+        Constraint::Length(5),
+    ])
+    .areas(area);
+    frame.render_widget(Paragraph::new(label), label_area);
+    frame.render_widget(
+        Sparkline::default()
+            .data(tail(buf, spark_area.width))
+            .max(max)
+            .style(Style::default().fg(fg)),
+        spark_area,
+    );
+    let last = buf.back().copied().unwrap_or(0);
+    let value = if label == "pow" {
+        format!("{:>4}W", last)
+    } else {
+        format!("{:>4}%", last)
+    };
+    frame.render_widget(Paragraph::new(value), value_area);
+}
+
+fn draw_processes(frame: &mut Frame, app: &mut App, area: Rect) {
+    let header = Row::new(SortColumn::ALL.iter().map(|col| {
+        let mut title = col.title().to_string();
+        if *col == app.sort {
+            title.push(if app.sort_desc { '▼' } else { '▲' });
+            Cell::from(title).style(Style::default().add_modifier(Modifier::BOLD))
+        } else {
+            Cell::from(title)
+        }
+    }))
+    .style(Style::default().add_modifier(Modifier::REVERSED));
+    let rows = app.rows();
+    let rows = rows.iter().map(|row| {
+        let command = match row.kind {
+            RowKind::Process => row.command.clone(),
+            RowKind::Group { expanded: false } => format!("▸ {}", row.command),
+            RowKind::Group { expanded: true } => format!("▾ {}", row.command),
+            RowKind::Member => format!("  └ {}", row.command),
+        };
+        Row::new(vec![
+            Cell::from(row.user.clone()),
+            Cell::from(row.container.clone()),
+            Cell::from(row.owner.clone()),
+            Cell::from(row.job.map(|job| job.to_string()).unwrap_or_default()),
+            Cell::from(match row.container_pid {
+                Some(inner) => format!("{} [{}]", row.pid, inner),
+                None => row.pid.to_string(),
+            }),
+            Cell::from(gpu_list(&row.gpus)),
+            Cell::from(
+                row.memory
+                    .map_or("N/A".to_string(), |m| format!("{}MiB", m)),
+            ),
+            Cell::from(command),
+        ])
+    });
+    let title = match (&app.user_filter, app.grouped) {
+        (Some(user), false) => format!("processes (user: {})", user),
+        (Some(user), true) => format!("process groups (user: {})", user),
+        (None, false) => String::from("processes"),
+        (None, true) => String::from("process groups"),
+    };
+    let table = Table::new(
+        rows,
+        [
+            Constraint::Length(10),
+            Constraint::Length(20),
+            Constraint::Length(24),
+            Constraint::Length(10),
+            Constraint::Length(16),
+            Constraint::Length(7),
+            Constraint::Length(10),
+            Constraint::Min(10),
+        ],
+    )
+    .header(header)
+    .block(Block::default().borders(Borders::ALL).title(title))
+    .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED));
+    frame.render_stateful_widget(table, area, &mut app.table);
+}
+
+fn draw_footer(frame: &mut Frame, app: &App, area: Rect) {
+    let text = match &app.mode {
+        Mode::Filter(input) => format!(
+            "user: {}_  (Enter to apply, empty to clear, Esc to cancel)",
+            input
+        ),
+        _ => match &app.status {
+            Some(status) => status.clone(),
+            None => {
+                String::from("q quit  ↑↓ select  ←→ sort  r reverse  u filter user  g group  ⏎ expand  k TERM  K KILL")
+            }
+        },
+    };
+    frame.render_widget(Paragraph::new(text), area);
+}
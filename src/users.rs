@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+
+/// Largest buffer offered to the NSS lookups, which ask for more by failing
+/// with `ERANGE`.
+const MAX_BUF: usize = 1 << 20;
+
+/// Calls a reentrant NSS lookup with a buffer that grows until the entry
+/// fits, returning `read` of the entry found.
+fn lookup<E, T>(
+    call: impl Fn(*mut E, *mut libc::c_char, usize, *mut *mut E) -> libc::c_int,
+    read: impl Fn(&E) -> T,
+) -> Option<T> {
+    let mut buf = vec![0 as libc::c_char; 1024];
+    loop {
+        let mut entry: E = unsafe { std::mem::zeroed() };
+        let mut result = std::ptr::null_mut();
+        let rc = call(&mut entry, buf.as_mut_ptr(), buf.len(), &mut result);
+        if rc == libc::ERANGE && buf.len() < MAX_BUF {
+            buf.resize(buf.len() * 2, 0);
+            continue;
+        }
+        return (rc == 0 && !result.is_null()).then(|| read(&entry));
+    }
+}
+
+fn to_string(name: *const libc::c_char) -> String {
+    unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// The uid of the user called `name`, looked up through NSS so that LDAP and
+/// other directories count as well as `/etc/passwd`.
+pub fn uid_by_name(name: &str) -> Option<u32> {
+    let name = CString::new(name).ok()?;
+    lookup(
+        |pwd, buf, len, result| unsafe { libc::getpwnam_r(name.as_ptr(), pwd, buf, len, result) },
+        |pwd: &libc::passwd| pwd.pw_uid,
+    )
+}
+
+/// The name of user `uid`.
+pub fn user_name(uid: u32) -> Option<String> {
+    lookup(
+        |pwd, buf, len, result| unsafe { libc::getpwuid_r(uid, pwd, buf, len, result) },
+        |pwd: &libc::passwd| to_string(pwd.pw_name),
+    )
+}
+
+/// The name of group `gid`.
+pub fn group_name(gid: u32) -> Option<String> {
+    lookup(
+        |grp, buf, len, result| unsafe { libc::getgrgid_r(gid, grp, buf, len, result) },
+        |grp: &libc::group| to_string(grp.gr_name),
+    )
+}
+
+/// Remembers user and group names, including their absence, since NSS
+/// lookups may go over the network.
+///
+/// ```
+/// use gmon::users::Names;
+///
+/// let mut names = Names::default();
+/// assert_eq!(names.user(0).as_deref(), Some("root"));
+/// ```
+#[derive(Debug, Default)]
+pub struct Names {
+    users: HashMap<u32, Option<String>>,
+    groups: HashMap<u32, Option<String>>,
+}
+
+impl Names {
+    pub fn user(&mut self, uid: u32) -> Option<String> {
+        self.users
+            .entry(uid)
+            .or_insert_with(|| user_name(uid))
+            .clone()
+    }
+
+    pub fn group(&mut self, gid: u32) -> Option<String> {
+        self.groups
+            .entry(gid)
+            .or_insert_with(|| group_name(gid))
+            .clone()
+    }
+}
@@ -3,10 +3,9 @@ use std::process::Command;
 
 pub fn exec(prog: &str, args: Option<Vec<&str>>) -> Result<String, Error> {
     let mut cmd = Command::new(prog);
-    if args.is_some() {
-        cmd.args(args.unwrap());
+    if let Some(args) = args {
+        cmd.args(args);
     }
     let out = cmd.output()?;
     Ok(String::from_utf8(out.stdout).unwrap())
 }
-
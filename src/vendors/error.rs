@@ -5,6 +5,32 @@ use thiserror::Error;
 pub enum BackendError {
     #[error("failed to initialize nvml with nvml_wrapper")]
     NvmlInitError,
+    #[error("not supported by this device")]
+    NotSupported,
+    #[error("insufficient permissions")]
+    NoPermission,
+    #[error("gpu has fallen off the bus or is otherwise inaccessible")]
+    GpuLost,
     #[error("failed due to nvml_wrapper error {0}")]
-    NvmlWrapperError(#[from] NvmlError),
+    NvmlWrapperError(String),
+}
+
+impl From<NvmlError> for BackendError {
+    fn from(e: NvmlError) -> Self {
+        match e {
+            NvmlError::NotSupported => BackendError::NotSupported,
+            NvmlError::NoPermission => BackendError::NoPermission,
+            NvmlError::GpuLost => BackendError::GpuLost,
+            e => BackendError::NvmlWrapperError(e.to_string()),
+        }
+    }
+}
+
+/// A device that could not be reached while iterating a provider.
+#[derive(Error, Debug)]
+#[error("gpu {index}: {error}")]
+pub struct DeviceError {
+    pub index: u32,
+    #[source]
+    pub error: BackendError,
 }
@@ -0,0 +1,216 @@
+use super::error::{BackendError, DeviceError};
+use super::traits::{GpuInfo, GpuProviderInfo};
+use super::types::{
+    ClockInfo, Counters, CudaVersion, MemoryInfo, PowerInfo, ProcessInfo, Topology, Value,
+};
+
+/// A GPU with fixed readings, served by [`FakeGpuProvider`].
+#[derive(Debug, Clone)]
+pub struct FakeGpu {
+    pub index: u32,
+    pub uuid: String,
+    pub name: String,
+    pub pci_bus_id: String,
+    pub utilization: u32,
+    pub temperature: u32,
+    /// Power draw in milliwatts.
+    pub power_read: u32,
+    /// Power limit in milliwatts.
+    pub power_limit: u32,
+    /// Total memory in MiB.
+    pub memory_total: u64,
+    /// Used memory in MiB.
+    pub memory_used: u64,
+    pub processes: Vec<ProcessInfo>,
+    /// UUIDs of MIG instances, for a card in MIG mode.
+    pub mig_uuids: Vec<String>,
+    /// Reports the device as fallen off the bus.
+    pub lost: bool,
+}
+
+impl FakeGpu {
+    /// An idle 24GB card with the given index.
+    pub fn idle(index: u32) -> Self {
+        FakeGpu {
+            index,
+            uuid: format!("GPU-00000000-0000-0000-0000-{:012}", index),
+            name: String::from("Fake GPU"),
+            pci_bus_id: format!("00000000:{:02X}:00.0", index + 1),
+            utilization: 0,
+            temperature: 30,
+            power_read: 25_000,
+            power_limit: 250_000,
+            memory_total: 24_576,
+            memory_used: 0,
+            processes: vec![],
+            mig_uuids: vec![],
+            lost: false,
+        }
+    }
+}
+
+/// GPU provider that reports a fixed set of devices without touching any
+/// driver.
+///
+/// Useful for embedding gmon in programs that must run on machines without
+/// GPUs, and for exercising renderers and exporters.
+///
+/// ```
+/// use gmon::vendors::fake::{FakeGpu, FakeGpuProvider};
+/// use gmon::vendors::traits::{GpuInfo, GpuProviderInfo};
+///
+/// let provider = FakeGpuProvider::new(vec![FakeGpu::idle(0), FakeGpu::idle(1)]);
+/// assert_eq!(provider.device_count()?.val, 2);
+/// for gpu in provider.gpu_iter()? {
+///     assert_eq!(gpu?.utilization()?.val, 0);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct FakeGpuProvider {
+    gpus: Vec<FakeGpu>,
+    links: Vec<(u32, u32, Topology)>,
+}
+
+impl FakeGpuProvider {
+    pub fn new(gpus: Vec<FakeGpu>) -> Self {
+        Self {
+            gpus,
+            links: vec![],
+        }
+    }
+
+    /// Reports devices `a` and `b` as connected by `level`; the topology of
+    /// pairs never linked is not supported.
+    pub fn with_link(mut self, a: u32, b: u32, level: Topology) -> Self {
+        self.links.push((a, b, level));
+        self
+    }
+
+    /// A provider with `count` idle devices.
+    pub fn with_idle_devices(count: u32) -> Self {
+        Self::new((0..count).map(FakeGpu::idle).collect())
+    }
+}
+
+fn reach(gpu: &FakeGpu) -> Result<&FakeGpu, DeviceError> {
+    if gpu.lost {
+        Err(DeviceError {
+            index: gpu.index,
+            error: BackendError::GpuLost,
+        })
+    } else {
+        Ok(gpu)
+    }
+}
+
+pub type FakeGpuIter<'a> = std::iter::Map<
+    std::slice::Iter<'a, FakeGpu>,
+    fn(&'a FakeGpu) -> Result<&'a FakeGpu, DeviceError>,
+>;
+
+impl<'a> GpuProviderInfo<'a, &'a FakeGpu> for FakeGpuProvider {
+    type IterType = FakeGpuIter<'a>;
+
+    fn gpu_iter(&'a self) -> Result<Self::IterType, BackendError> {
+        Ok(self.gpus.iter().map(reach as fn(&'a FakeGpu) -> _))
+    }
+
+    fn gpu(&'a self, index: u32) -> Result<&'a FakeGpu, DeviceError> {
+        match self.gpus.get(index as usize) {
+            Some(gpu) => reach(gpu),
+            None => Err(DeviceError {
+                index,
+                error: BackendError::NotSupported,
+            }),
+        }
+    }
+
+    fn driver_version(&self) -> Result<Value<String>, BackendError> {
+        Ok(Value::from(String::from("0.0.0")))
+    }
+
+    fn cuda_version(&self) -> Result<CudaVersion, BackendError> {
+        Ok(CudaVersion {
+            major: Value::from(0),
+            minor: Value::from(0),
+        })
+    }
+
+    fn device_count(&self) -> Result<Value<u32>, BackendError> {
+        Ok(Value::from(self.gpus.len() as u32))
+    }
+
+    fn topology(&'a self, a: u32, b: u32) -> Result<Topology, BackendError> {
+        self.links
+            .iter()
+            .find(|(x, y, _)| (*x, *y) == (a, b) || (*x, *y) == (b, a))
+            .map(|(_, _, level)| *level)
+            .ok_or(BackendError::NotSupported)
+    }
+}
+
+impl<'a> GpuInfo<'a> for &'a FakeGpu {
+    fn index(&self) -> Result<Value<u32>, BackendError> {
+        Ok(Value::from(self.index))
+    }
+
+    fn uuid(&self) -> Result<Value<String>, BackendError> {
+        Ok(Value::from(self.uuid.clone()))
+    }
+
+    fn name(&self) -> Result<Value<String>, BackendError> {
+        Ok(Value::from(self.name.clone()))
+    }
+
+    fn pci_bus_id(&self) -> Result<Value<String>, BackendError> {
+        Ok(Value::from(self.pci_bus_id.clone()))
+    }
+
+    fn utilization(&self) -> Result<Value<u32>, BackendError> {
+        Ok(Value::from(self.utilization))
+    }
+
+    fn temperature(&self) -> Result<Value<u32>, BackendError> {
+        Ok(Value::from(self.temperature).set_unit("°C"))
+    }
+
+    fn power_info(&self) -> Result<PowerInfo, BackendError> {
+        Ok(PowerInfo {
+            read: Value::from(self.power_read).set_unit("mW"),
+            limit: Value::from(self.power_limit).set_unit("mW"),
+            limit_default: Value::from(self.power_limit).set_unit("mW"),
+        })
+    }
+
+    fn memory_info(&self) -> Result<MemoryInfo, BackendError> {
+        Ok(MemoryInfo {
+            total: Value::from(self.memory_total).set_unit("MiB"),
+            used: Value::from(self.memory_used).set_unit("MiB"),
+        })
+    }
+
+    fn clocks(&self) -> Result<ClockInfo, BackendError> {
+        Ok(ClockInfo {
+            graphics: Value::from(210).set_unit("MHz"),
+            sm: Value::from(210).set_unit("MHz"),
+            memory: Value::from(405).set_unit("MHz"),
+        })
+    }
+
+    fn counters(&self) -> Result<Counters, BackendError> {
+        Ok(Counters {
+            energy: Some(Value::from(0).set_unit("mJ")),
+            ecc_corrected: Some(Value::from(0)),
+            ecc_uncorrected: Some(Value::from(0)),
+        })
+    }
+
+    fn processes(&self) -> Result<Vec<ProcessInfo>, BackendError> {
+        Ok(self.processes.clone())
+    }
+
+    fn mig_uuids(&self) -> Result<Vec<String>, BackendError> {
+        Ok(self.mig_uuids.clone())
+    }
+}
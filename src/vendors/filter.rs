@@ -0,0 +1,128 @@
+use std::collections::BTreeSet;
+use std::str::FromStr;
+
+use regex::Regex;
+use thiserror::Error;
+
+use crate::snapshot::Device;
+
+#[derive(Error, Debug)]
+pub enum FilterError {
+    #[error("invalid GPU list \"{0}\", expected indices and ranges such as 0,2-3")]
+    Indices(String),
+}
+
+/// A set of device indices, written as `0,2-3`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexSet(pub BTreeSet<u32>);
+
+impl FromStr for IndexSet {
+    type Err = FilterError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || FilterError::Indices(s.to_string());
+        let mut set = BTreeSet::new();
+        for part in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            match part.split_once('-') {
+                Some((lo, hi)) => {
+                    let lo = lo.trim().parse::<u32>().map_err(|_| invalid())?;
+                    let hi = hi.trim().parse::<u32>().map_err(|_| invalid())?;
+                    if lo > hi {
+                        return Err(invalid());
+                    }
+                    set.extend(lo..=hi);
+                }
+                None => {
+                    set.insert(part.parse::<u32>().map_err(|_| invalid())?);
+                }
+            }
+        }
+        if set.is_empty() {
+            return Err(invalid());
+        }
+        Ok(IndexSet(set))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Activity {
+    /// Running processes or reporting any utilization.
+    Busy,
+    Idle,
+}
+
+/// Restricts which devices a [`Collector`](crate::collector::Collector)
+/// samples.
+///
+/// Criteria are checked from cheapest to dearest: the index before any
+/// query, UUID and name once per device (they are cached), and activity
+/// after the dynamic readings. A device left out by the index, UUID or name
+/// costs nothing to skip. All criteria must hold.
+///
+/// ```
+/// use gmon::collector::Collector;
+/// use gmon::vendors::fake::FakeGpuProvider;
+/// use gmon::vendors::filter::DeviceFilter;
+///
+/// let provider = FakeGpuProvider::with_idle_devices(8);
+/// let filter = DeviceFilter {
+///     indices: Some("0,2-3".parse()?),
+///     ..DeviceFilter::default()
+/// };
+/// let mut collector = Collector::new(&provider, false).with_filter(filter);
+/// let snapshot = collector.sample()?;
+/// let indices: Vec<u32> = snapshot.devices.iter().map(|d| d.index).collect();
+/// assert_eq!(indices, vec![0, 2, 3]);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct DeviceFilter {
+    pub indices: Option<IndexSet>,
+    /// Full UUIDs or prefixes, e.g. `GPU-5e1f`; a prefix selects every
+    /// device whose UUID starts with it.
+    pub uuids: Vec<String>,
+    pub name: Option<Regex>,
+    pub activity: Option<Activity>,
+}
+
+impl DeviceFilter {
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_none()
+            && self.uuids.is_empty()
+            && self.name.is_none()
+            && self.activity.is_none()
+    }
+
+    pub fn matches_index(&self, index: u32) -> bool {
+        match &self.indices {
+            Some(set) => set.0.contains(&index),
+            None => true,
+        }
+    }
+
+    /// Whether the fixed identity of a device passes; unknown values only
+    /// pass when they are not filtered on.
+    pub fn matches_identity(&self, uuid: Option<&str>, name: Option<&str>) -> bool {
+        let uuid_ok = self.uuids.is_empty()
+            || uuid.is_some_and(|uuid| {
+                self.uuids
+                    .iter()
+                    .any(|want| uuid.starts_with(want.as_str()))
+            });
+        let name_ok = match &self.name {
+            Some(re) => name.is_some_and(|name| re.is_match(name)),
+            None => true,
+        };
+        uuid_ok && name_ok
+    }
+
+    pub fn matches_activity(&self, device: &Device) -> bool {
+        let busy =
+            !device.processes.is_empty() || device.utilization.as_ref().is_some_and(|u| u.val > 0);
+        match self.activity {
+            Some(Activity::Busy) => device.is_available() && busy,
+            Some(Activity::Idle) => device.is_available() && !busy,
+            None => true,
+        }
+    }
+}
@@ -1,4 +1,6 @@
 pub mod error;
+pub mod fake;
+pub mod filter;
 pub mod nvidia;
 pub mod traits;
 pub mod types;
@@ -0,0 +1,48 @@
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint};
+use std::ptr;
+
+use nvml_wrapper::error::{nvml_try, NvmlError};
+use nvml_wrapper_sys::bindings::{
+    nvmlDevice_t, NvmlLib, NVML_DEVICE_MIG_ENABLE, NVML_DEVICE_UUID_V2_BUFFER_SIZE,
+};
+
+/// Opens the NVML library a second time for the MIG calls `nvml-wrapper`
+/// does not expose; it shares the state of the already initialized one.
+pub(crate) fn load(lib: &str) -> Option<NvmlLib> {
+    let sys = unsafe { NvmlLib::new(lib) }.ok()?;
+    // Drivers before MIG lack the symbols, and calling them would panic.
+    let supported = sys.nvmlDeviceGetMigMode.is_ok()
+        && sys.nvmlDeviceGetMaxMigDeviceCount.is_ok()
+        && sys.nvmlDeviceGetMigDeviceHandleByIndex.is_ok()
+        && sys.nvmlDeviceGetUUID.is_ok();
+    supported.then_some(sys)
+}
+
+/// The UUIDs of the MIG devices of `device`, none unless MIG is enabled.
+pub(crate) fn uuids(sys: &NvmlLib, device: nvmlDevice_t) -> Result<Vec<String>, NvmlError> {
+    let (mut current, mut pending) = (0, 0);
+    match nvml_try(unsafe { sys.nvmlDeviceGetMigMode(device, &mut current, &mut pending) }) {
+        Ok(()) if current == NVML_DEVICE_MIG_ENABLE => {}
+        Ok(()) | Err(NvmlError::NotSupported) => return Ok(vec![]),
+        Err(e) => return Err(e),
+    }
+    let mut count: c_uint = 0;
+    nvml_try(unsafe { sys.nvmlDeviceGetMaxMigDeviceCount(device, &mut count) })?;
+    let mut uuids = vec![];
+    for index in 0..count {
+        let mut mig = ptr::null_mut();
+        // Slots without a MIG device in them are not found.
+        match nvml_try(unsafe { sys.nvmlDeviceGetMigDeviceHandleByIndex(device, index, &mut mig) })
+        {
+            Ok(()) => {}
+            Err(NvmlError::NotFound) => continue,
+            Err(e) => return Err(e),
+        }
+        let mut buf = [0 as c_char; NVML_DEVICE_UUID_V2_BUFFER_SIZE as usize];
+        nvml_try(unsafe { sys.nvmlDeviceGetUUID(mig, buf.as_mut_ptr(), buf.len() as c_uint) })?;
+        let uuid = unsafe { CStr::from_ptr(buf.as_ptr()) };
+        uuids.push(uuid.to_string_lossy().into_owned());
+    }
+    Ok(uuids)
+}
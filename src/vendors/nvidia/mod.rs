@@ -1,140 +1,281 @@
-use nvml_wrapper::{self, enum_wrappers::device::TemperatureSensor, Nvml};
+use nvml_wrapper::{
+    self,
+    enum_wrappers::device::{Clock, TemperatureSensor, TopologyLevel},
+    enums::device::{SampleValue, UsedGpuMemory},
+    structs::device::FieldId,
+    sys_exports::field_id::{
+        NVML_FI_DEV_ECC_DBE_VOL_TOTAL, NVML_FI_DEV_ECC_SBE_VOL_TOTAL,
+        NVML_FI_DEV_TOTAL_ENERGY_CONSUMPTION,
+    },
+    Nvml,
+};
+use nvml_wrapper_sys::bindings::NvmlLib;
 
-use super::error::BackendError;
+use super::error::{BackendError, DeviceError};
 use super::traits::{GpuInfo, GpuProviderInfo};
-use super::types::{MemoryInfo, PowerInfo, Value};
+use super::types::{
+    ClockInfo, Counters, CudaVersion, MemoryInfo, PowerInfo, ProcessInfo, Topology, Value,
+};
 
-pub mod nvml_utils;
+mod mig;
+mod nvml_utils;
 
+/// `NVML_NVLINK_MAX_LINKS`; devices report `NotSupported` for links they lack.
+const MAX_NVLINKS: u32 = 18;
+
+/// Iterator over the GPUs visible to an [`NvGpuProvider`].
 pub struct NvGpuIter<'a> {
     index: u32,
+    count: u32,
     nvml: &'a Nvml,
+    sys: Option<&'a NvmlLib>,
 }
 
 impl<'a> Iterator for NvGpuIter<'a> {
-    type Item = NvGpu<'a>;
+    type Item = Result<NvGpu<'a>, DeviceError>;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.index < self.nvml.device_count().unwrap() {
-            let gpu = NvGpu::get_by_id(&self.nvml, self.index);
+        if self.index < self.count {
+            let index = self.index;
             self.index += 1;
-            Some(gpu)
+            Some(
+                NvGpu::get_by_id(self.nvml, self.sys, index)
+                    .map_err(|error| DeviceError { index, error }),
+            )
         } else {
             None
         }
     }
 }
 
-pub fn gpu_iter<'a>(nvml: &'a Nvml) -> NvGpuIter<'a> {
-    NvGpuIter { index: 0, nvml }
-}
-
-pub struct CudaVersion {
-    pub major: Value<i32>,
-    pub minor: Value<i32>,
+pub(crate) fn gpu_iter<'a>(
+    nvml: &'a Nvml,
+    sys: Option<&'a NvmlLib>,
+) -> Result<NvGpuIter<'a>, BackendError> {
+    let count = nvml.device_count().map_err(BackendError::from)?;
+    Ok(NvGpuIter {
+        index: 0,
+        count,
+        nvml,
+        sys,
+    })
 }
 
+/// GPU provider backed by NVML.
+///
+/// ```no_run
+/// use gmon::vendors::nvidia::NvGpuProvider;
+/// use gmon::vendors::traits::{GpuInfo, GpuProviderInfo};
+///
+/// let provider = NvGpuProvider::new()?;
+/// for gpu in provider.gpu_iter()? {
+///     let gpu = gpu?;
+///     println!("{}: {}", gpu.index()?.val, gpu.name()?.val);
+/// }
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
 pub struct NvGpuProvider {
     nvml: Nvml,
+    lib: String,
+    sys: Option<NvmlLib>,
+}
+
+/// Outcome of trying to load one NVML library candidate.
+#[derive(Debug, Clone)]
+pub struct LibAttempt {
+    pub candidate: String,
+    /// `None` if the library was loaded.
+    pub error: Option<String>,
 }
 
 impl NvGpuProvider {
+    /// Loads NVML from the default library locations.
     pub fn new() -> Result<Self, BackendError> {
-        Ok(Self {
-            nvml: nvml_utils::nvml_initiate(None)?,
-        })
+        let (nvml, lib) = nvml_utils::nvml_initiate(None)?;
+        Ok(Self::loaded(nvml, lib))
     }
 
-    // pub fn new_with_lib(lib_candidates: &[String]) -> Result<Self, BackendError> {
-    //     Ok(Self {
-    //         nvml: nvml_utils::nvml_initiate(Some(lib_candidates))?,
-    //     })
-    // }
+    /// Loads NVML from the first of `lib_candidates` that works, falling back
+    /// to the default library locations.
+    pub fn new_with_lib(lib_candidates: &[String]) -> Result<Self, BackendError> {
+        let (nvml, lib) = nvml_utils::nvml_initiate(Some(lib_candidates))?;
+        Ok(Self::loaded(nvml, lib))
+    }
 
-    pub fn cuda_version(&self) -> Result<CudaVersion, BackendError> {
-        let version = self
-            .nvml
-            .sys_cuda_driver_version()
-            .map_err(BackendError::NvmlWrapperError)?;
-        Ok(CudaVersion {
-            major: Value::from(nvml_wrapper::cuda_driver_version_major(version)),
-            minor: Value::from(nvml_wrapper::cuda_driver_version_minor(version)),
-        })
+    /// Like [`NvGpuProvider::new_with_lib`], but also reports every candidate
+    /// that was tried.
+    pub fn probe(lib_candidates: &[String]) -> (Result<Self, BackendError>, Vec<LibAttempt>) {
+        let (res, attempts) = nvml_utils::nvml_probe(Some(lib_candidates));
+        (res.map(|(nvml, lib)| Self::loaded(nvml, lib)), attempts)
     }
+
+    fn loaded(nvml: Nvml, lib: String) -> Self {
+        let sys = mig::load(&lib);
+        Self { nvml, lib, sys }
+    }
+
+    /// The library NVML was loaded from.
+    pub fn library(&self) -> &str {
+        &self.lib
+    }
+
+    pub fn nvml_version(&self) -> Result<Value<String>, BackendError> {
+        let version = self.nvml.sys_nvml_version().map_err(BackendError::from)?;
+        Ok(Value::from(version))
+    }
+}
+
+/// Access state of one `/dev/nvidia*` device node.
+#[derive(Debug, Clone)]
+pub struct DeviceNode {
+    pub path: std::path::PathBuf,
+    /// `None` if the node can be opened for reading and writing.
+    pub error: Option<String>,
+}
+
+/// Lists the NVIDIA device nodes under `/dev` and checks whether the current
+/// user can open them.
+pub fn device_nodes() -> Vec<DeviceNode> {
+    let mut nodes: Vec<DeviceNode> = match std::fs::read_dir("/dev") {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with("nvidia"))
+            .filter(|entry| entry.file_type().map(|t| !t.is_dir()).unwrap_or(false))
+            .map(|entry| {
+                let path = entry.path();
+                let error = std::fs::OpenOptions::new()
+                    .read(true)
+                    .write(true)
+                    .open(&path)
+                    .err()
+                    .map(|e| e.to_string());
+                DeviceNode { path, error }
+            })
+            .collect(),
+        Err(_) => vec![],
+    };
+    nodes.sort_by(|a, b| a.path.cmp(&b.path));
+    nodes
 }
 
 impl<'a> GpuProviderInfo<'a, NvGpu<'a>> for NvGpuProvider {
     type IterType = NvGpuIter<'a>;
 
-    fn gpu_iter(&'a self) -> Self::IterType {
-        gpu_iter(&self.nvml)
+    fn gpu_iter(&'a self) -> Result<Self::IterType, BackendError> {
+        gpu_iter(&self.nvml, self.sys.as_ref())
+    }
+
+    fn gpu(&'a self, index: u32) -> Result<NvGpu<'a>, DeviceError> {
+        NvGpu::get_by_id(&self.nvml, self.sys.as_ref(), index)
+            .map_err(|error| DeviceError { index, error })
     }
 
     fn driver_version(&self) -> Result<Value<String>, BackendError> {
+        let version = self.nvml.sys_driver_version().map_err(BackendError::from)?;
+        Ok(Value::from(version))
+    }
+
+    fn cuda_version(&self) -> Result<CudaVersion, BackendError> {
         let version = self
             .nvml
-            .sys_driver_version()
-            .map_err(BackendError::NvmlWrapperError)?;
-        Ok(Value::from(version))
+            .sys_cuda_driver_version()
+            .map_err(BackendError::from)?;
+        Ok(CudaVersion {
+            major: Value::from(nvml_wrapper::cuda_driver_version_major(version)),
+            minor: Value::from(nvml_wrapper::cuda_driver_version_minor(version)),
+        })
     }
 
     fn device_count(&self) -> Result<Value<u32>, BackendError> {
-        let count = self
-            .nvml
-            .device_count()
-            .map_err(BackendError::NvmlWrapperError)?;
+        let count = self.nvml.device_count().map_err(BackendError::from)?;
         Ok(Value::from(count))
     }
+
+    fn topology(&'a self, a: u32, b: u32) -> Result<Topology, BackendError> {
+        let first = self.nvml.device_by_index(a).map_err(BackendError::from)?;
+        let second = self.nvml.device_by_index(b).map_err(BackendError::from)?;
+        let peer = second.pci_info().map_err(BackendError::from)?.bus_id;
+        let nvlinked = (0..MAX_NVLINKS).any(|link| {
+            let link = first.link_wrapper_for(link);
+            link.is_active().unwrap_or(false)
+                && link
+                    .remote_pci_info()
+                    .is_ok_and(|remote| remote.bus_id == peer)
+        });
+        if nvlinked {
+            return Ok(Topology::NvLink);
+        }
+        let level = first
+            .topology_common_ancestor(second)
+            .map_err(BackendError::from)?;
+        Ok(match level {
+            TopologyLevel::Internal => Topology::Board,
+            TopologyLevel::Single => Topology::Switch,
+            TopologyLevel::Multiple => Topology::Switches,
+            TopologyLevel::HostBridge => Topology::HostBridge,
+            TopologyLevel::Node => Topology::Node,
+            TopologyLevel::System => Topology::System,
+        })
+    }
 }
 
+/// A single NVIDIA GPU, borrowed from an [`NvGpuProvider`].
 pub struct NvGpu<'a> {
-    pub gpu: nvml_wrapper::Device<'a>,
+    gpu: nvml_wrapper::Device<'a>,
+    sys: Option<&'a NvmlLib>,
 }
 
 impl<'a> NvGpu<'a> {
-    pub fn get_by_id(nvml: &'a Nvml, id: u32) -> Self {
-        let gpu = nvml.device_by_index(id).unwrap();
-        assert!(id == gpu.index().unwrap()); // TODO:
-        Self { gpu }
+    pub(crate) fn get_by_id(
+        nvml: &'a Nvml,
+        sys: Option<&'a NvmlLib>,
+        id: u32,
+    ) -> Result<Self, BackendError> {
+        let gpu = nvml.device_by_index(id).map_err(BackendError::from)?;
+        Ok(Self { gpu, sys })
     }
 }
 
 impl<'a> GpuInfo<'a> for NvGpu<'a> {
     fn index(&self) -> Result<Value<u32>, BackendError> {
-        let idx = self.gpu.index().map_err(BackendError::NvmlWrapperError)?;
+        let idx = self.gpu.index().map_err(BackendError::from)?;
         Ok(Value::from(idx))
     }
 
+    fn uuid(&self) -> Result<Value<String>, BackendError> {
+        let uuid = self.gpu.uuid().map_err(BackendError::from)?;
+        Ok(Value::from(uuid))
+    }
+
     fn name(&self) -> Result<Value<String>, BackendError> {
-        let name = self.gpu.name().map_err(BackendError::NvmlWrapperError)?;
+        let name = self.gpu.name().map_err(BackendError::from)?;
         Ok(Value::from(name))
     }
 
+    fn pci_bus_id(&self) -> Result<Value<String>, BackendError> {
+        let pci = self.gpu.pci_info().map_err(BackendError::from)?;
+        Ok(Value::from(pci.bus_id))
+    }
+
     fn power_info(&self) -> Result<PowerInfo, BackendError> {
-        let read = self
-            .gpu
-            .power_usage()
-            .map_err(BackendError::NvmlWrapperError)?;
+        let read = self.gpu.power_usage().map_err(BackendError::from)?;
         let limit = self
             .gpu
             .power_management_limit()
-            .map_err(BackendError::NvmlWrapperError)?;
-        let limit_constraints = self
+            .map_err(BackendError::from)?;
+        let limit_default = self
             .gpu
-            .power_management_limit_constraints()
-            .map_err(BackendError::NvmlWrapperError)?;
+            .power_management_limit_default()
+            .map_err(BackendError::from)?;
         Ok(PowerInfo {
             read: Value::from(read).set_unit("mW"),
             limit: Value::from(limit).set_unit("mW"),
-            limit_default: Value::from(limit_constraints.max_limit).set_unit("mW"),
+            limit_default: Value::from(limit_default).set_unit("mW"),
         })
     }
 
     fn utilization(&self) -> Result<Value<u32>, BackendError> {
-        let util = self
-            .gpu
-            .utilization_rates()
-            .map_err(BackendError::NvmlWrapperError)?;
+        let util = self.gpu.utilization_rates().map_err(BackendError::from)?;
         Ok(Value::from(util.gpu))
     }
 
@@ -142,18 +283,84 @@ impl<'a> GpuInfo<'a> for NvGpu<'a> {
         let temp = self
             .gpu
             .temperature(TemperatureSensor::Gpu)
-            .map_err(BackendError::NvmlWrapperError)?;
+            .map_err(BackendError::from)?;
         Ok(Value::from(temp).set_unit("°C"))
     }
 
     fn memory_info(&self) -> Result<MemoryInfo, BackendError> {
-        let mem_info = self
-            .gpu
-            .memory_info()
-            .map_err(BackendError::NvmlWrapperError)?;
+        let mem_info = self.gpu.memory_info().map_err(BackendError::from)?;
         Ok(MemoryInfo {
-            total: Value::from(mem_info.total).set_unit("MB"),
-            used: Value::from(mem_info.used).set_unit("MB"),
+            total: Value::from(mem_info.total / 1024 / 1024).set_unit("MiB"),
+            used: Value::from(mem_info.used / 1024 / 1024).set_unit("MiB"),
+        })
+    }
+
+    fn clocks(&self) -> Result<ClockInfo, BackendError> {
+        let clock = |clock_type| {
+            self.gpu
+                .clock_info(clock_type)
+                .map(|mhz| Value::from(mhz).set_unit("MHz"))
+                .map_err(BackendError::from)
+        };
+        Ok(ClockInfo {
+            graphics: clock(Clock::Graphics)?,
+            sm: clock(Clock::SM)?,
+            memory: clock(Clock::Memory)?,
         })
     }
+
+    fn counters(&self) -> Result<Counters, BackendError> {
+        let samples = self
+            .gpu
+            .field_values_for(&[
+                FieldId(NVML_FI_DEV_TOTAL_ENERGY_CONSUMPTION),
+                FieldId(NVML_FI_DEV_ECC_SBE_VOL_TOTAL),
+                FieldId(NVML_FI_DEV_ECC_DBE_VOL_TOTAL),
+            ])
+            .map_err(BackendError::from)?;
+        let mut values = samples.into_iter().map(|sample| match sample {
+            Ok(sample) => match sample.value {
+                Ok(SampleValue::U64(v)) => Some(v),
+                Ok(SampleValue::U32(v)) => Some(v as u64),
+                Ok(SampleValue::I64(v)) => u64::try_from(v).ok(),
+                Ok(SampleValue::F64(v)) => Some(v as u64),
+                Err(_) => None,
+            },
+            Err(_) => None,
+        });
+        Ok(Counters {
+            energy: values
+                .next()
+                .flatten()
+                .map(|v| Value::from(v).set_unit("mJ")),
+            ecc_corrected: values.next().flatten().map(Value::from),
+            ecc_uncorrected: values.next().flatten().map(Value::from),
+        })
+    }
+
+    fn processes(&self) -> Result<Vec<ProcessInfo>, BackendError> {
+        let procs = self
+            .gpu
+            .running_compute_processes()
+            .map_err(BackendError::from)?;
+        Ok(procs
+            .into_iter()
+            .map(|p| ProcessInfo {
+                pid: p.pid,
+                used_memory: match p.used_gpu_memory {
+                    UsedGpuMemory::Used(used) => {
+                        Some(Value::from(used / 1024 / 1024).set_unit("MiB"))
+                    }
+                    UsedGpuMemory::Unavailable => None,
+                },
+            })
+            .collect())
+    }
+
+    fn mig_uuids(&self) -> Result<Vec<String>, BackendError> {
+        match self.sys {
+            Some(sys) => mig::uuids(sys, unsafe { self.gpu.handle() }).map_err(BackendError::from),
+            None => Ok(vec![]),
+        }
+    }
 }
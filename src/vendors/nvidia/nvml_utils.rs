@@ -4,12 +4,22 @@ use nvml_wrapper::error::NvmlError;
 use nvml_wrapper::Nvml;
 
 use super::super::error::BackendError;
+use super::LibAttempt;
 
 enum LibCandidate<'a> {
     Default,
     Custom(&'a str),
 }
 
+impl LibCandidate<'_> {
+    fn name(&self) -> &str {
+        match self {
+            LibCandidate::Default => "libnvidia-ml.so",
+            LibCandidate::Custom(path) => path,
+        }
+    }
+}
+
 static NVML_LIB_CAN: [LibCandidate; 2] = [
     LibCandidate::Default,
     LibCandidate::Custom("libnvidia-ml.so.1"),
@@ -22,9 +32,14 @@ fn init_nvml(lib: &LibCandidate) -> Result<Nvml, NvmlError> {
     }
 }
 
-pub fn nvml_initiate(custom_candidates: Option<&Vec<String>>) -> Result<Nvml, BackendError> {
+/// Tries each candidate in turn, custom ones first, and records the outcome
+/// of every attempt up to the first library that loads.
+pub fn nvml_probe(
+    custom_candidates: Option<&[String]>,
+) -> (Result<(Nvml, String), BackendError>, Vec<LibAttempt>) {
+    let mut attempts = vec![];
     for can in custom_candidates
-        .unwrap_or(&vec![])
+        .unwrap_or(&[])
         .iter()
         .map(|s| LibCandidate::Custom(s))
         .collect::<Vec<LibCandidate>>()
@@ -33,10 +48,21 @@ pub fn nvml_initiate(custom_candidates: Option<&Vec<String>>) -> Result<Nvml, Ba
     {
         match init_nvml(can) {
             Ok(nvml) => {
-                return Ok(nvml);
+                attempts.push(LibAttempt {
+                    candidate: can.name().to_string(),
+                    error: None,
+                });
+                return (Ok((nvml, can.name().to_string())), attempts);
             }
-            Err(_) => continue,
+            Err(e) => attempts.push(LibAttempt {
+                candidate: can.name().to_string(),
+                error: Some(e.to_string()),
+            }),
         }
     }
-    Err(BackendError::NvmlInitError)
+    (Err(BackendError::NvmlInitError), attempts)
+}
+
+pub fn nvml_initiate(custom_candidates: Option<&[String]>) -> Result<(Nvml, String), BackendError> {
+    nvml_probe(custom_candidates).0
 }
@@ -1,23 +1,52 @@
-use super::error::BackendError;
-use super::types::{MemoryInfo, PowerInfo, Value};
+use super::error::{BackendError, DeviceError};
+use super::types::{
+    ClockInfo, Counters, CudaVersion, MemoryInfo, PowerInfo, ProcessInfo, Topology, Value,
+};
 
 pub trait GpuProviderInfo<'a, T>
 where
     T: GpuInfo<'a>,
 {
-    type IterType: Iterator<Item = T>;
+    type IterType: Iterator<Item = Result<T, DeviceError>>;
 
-    fn gpu_iter(&'a self) -> Self::IterType;
+    fn gpu_iter(&'a self) -> Result<Self::IterType, BackendError>;
+
+    /// The device with index `index`. Providers that can should open it
+    /// without opening the devices before it.
+    fn gpu(&'a self, index: u32) -> Result<T, DeviceError> {
+        let not_found = || DeviceError {
+            index,
+            error: BackendError::NotSupported,
+        };
+        self.gpu_iter()
+            .map_err(|error| DeviceError { index, error })?
+            .nth(index as usize)
+            .unwrap_or_else(|| Err(not_found()))
+    }
 
     fn driver_version(&self) -> Result<Value<String>, BackendError>;
+    fn cuda_version(&self) -> Result<CudaVersion, BackendError>;
     fn device_count(&self) -> Result<Value<u32>, BackendError>;
+
+    /// How the devices with indices `a` and `b` are connected.
+    fn topology(&'a self, _a: u32, _b: u32) -> Result<Topology, BackendError> {
+        Err(BackendError::NotSupported)
+    }
 }
 
 pub trait GpuInfo<'a> {
     fn index(&self) -> Result<Value<u32>, BackendError>;
+    fn uuid(&self) -> Result<Value<String>, BackendError>;
     fn name(&self) -> Result<Value<String>, BackendError>;
+    /// The PCI bus ID, e.g. `00000000:3B:00.0`.
+    fn pci_bus_id(&self) -> Result<Value<String>, BackendError>;
     fn utilization(&self) -> Result<Value<u32>, BackendError>;
     fn temperature(&self) -> Result<Value<u32>, BackendError>;
     fn power_info(&self) -> Result<PowerInfo, BackendError>;
     fn memory_info(&self) -> Result<MemoryInfo, BackendError>;
+    fn clocks(&self) -> Result<ClockInfo, BackendError>;
+    fn counters(&self) -> Result<Counters, BackendError>;
+    fn processes(&self) -> Result<Vec<ProcessInfo>, BackendError>;
+    /// The UUIDs of the device's MIG instances, none if MIG is off.
+    fn mig_uuids(&self) -> Result<Vec<String>, BackendError>;
 }
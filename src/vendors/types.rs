@@ -1,4 +1,8 @@
-#[derive(Debug)]
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "Value_{T}")]
 pub struct Value<T: ToString> {
     pub val: T,
     pub unit: Option<String>,
@@ -14,15 +18,69 @@ impl<T: ToString> Value<T> {
     }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct MemoryInfo {
     pub total: Value<u64>,
     pub used: Value<u64>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct PowerInfo {
     pub read: Value<u32>,
     pub limit: Value<u32>,
     pub limit_default: Value<u32>,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ClockInfo {
+    pub graphics: Value<u32>,
+    pub sm: Value<u32>,
+    pub memory: Value<u32>,
+}
+
+/// Cumulative counters, read in a single batched query where the backend
+/// supports it.
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Counters {
+    pub energy: Option<Value<u64>>,
+    pub ecc_corrected: Option<Value<u64>>,
+    pub ecc_uncorrected: Option<Value<u64>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CudaVersion {
+    pub major: Value<i32>,
+    pub minor: Value<i32>,
+}
+
+/// A compute process as reported by the GPU driver.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ProcessInfo {
+    pub pid: u32,
+    pub used_memory: Option<Value<u64>>,
+}
+
+/// How directly two GPUs are connected, from closest to farthest.
+///
+/// The PCIe levels follow NVML's topology levels; peer-to-peer traffic is
+/// fastest over NVLink and degrades with each level after it.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum Topology {
+    /// A direct NVLink between the two.
+    NvLink,
+    /// On the same board, e.g. the halves of a dual-GPU card.
+    Board,
+    /// Behind a single PCIe switch.
+    Switch,
+    /// Behind several PCIe switches, without crossing a host bridge.
+    Switches,
+    /// Under the same host bridge.
+    HostBridge,
+    /// On the same NUMA node.
+    Node,
+    /// Across NUMA nodes.
+    System,
+}
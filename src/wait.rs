@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::io;
+use std::os::unix::process::CommandExt;
+use std::process::Command;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use gmon::claim::{Claim, ClaimStore};
+use gmon::collector::Collector;
+use gmon::select::{closest_gpus, free_gpus, sample_window, Criteria, FreeGpu};
+use gmon::vendors::traits::{GpuInfo, GpuProviderInfo};
+use gmon::vendors::types::Topology;
+
+use crate::claim_cmd::{self, claim_gpus};
+use crate::cli::WaitArgs;
+use crate::free::visible_devices;
+
+/// Polls until `args.count` GPUs are free, returning them, or `None` once
+/// the timeout passes. With `--claim` they are claimed for this process,
+/// which the command replaces.
+pub fn run<'a, P, G>(
+    collector: &mut Collector<'a, P>,
+    store: &ClaimStore,
+    args: &WaitArgs,
+    interval: Duration,
+) -> Result<Option<Vec<FreeGpu>>, claim_cmd::Error>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    let deadline = args
+        .timeout
+        .map(|secs| Instant::now() + Duration::from_secs_f64(secs));
+    let window = Duration::from_secs_f64(args.criteria.window);
+    let poll = Duration::from_secs_f64(args.poll);
+    let criteria = Criteria {
+        // Claiming a GPU the caller holds already would fail.
+        own_claims: !args.claim,
+        ..args.criteria.criteria()
+    };
+    // The topology is fixed, so each pair is only asked about once.
+    let mut links = HashMap::<(u32, u32), Option<Topology>>::new();
+    loop {
+        let samples = sample_window(collector, window, interval)?;
+        let gpus = free_gpus(&samples, &criteria);
+        let picked = if args.prefer_local && gpus.len() > args.count {
+            let provider = collector.provider();
+            for (i, a) in gpus.iter().enumerate() {
+                for b in &gpus[i + 1..] {
+                    links
+                        .entry((a.index, b.index))
+                        .or_insert_with(|| provider.topology(a.index, b.index).ok());
+                }
+            }
+            closest_gpus(&gpus, args.count, |a, b| {
+                links.get(&(a.min(b), a.max(b))).copied().flatten()
+            })
+        } else {
+            Some(gpus.into_iter().take(args.count).collect::<Vec<_>>())
+                .filter(|picked| picked.len() == args.count)
+        };
+        let picked = match picked {
+            Some(picked) if args.claim => {
+                let note = args.command.join(" ");
+                match claim_gpus(store, &picked, args.count, |uuid| Claim {
+                    note: Some(note.clone()),
+                    ..Claim::new(uuid, std::process::id())
+                }) {
+                    Ok(picked) => Some(picked),
+                    Err(claim_cmd::Error::NotEnough { .. }) => None,
+                    Err(e) => return Err(e),
+                }
+            }
+            picked => picked,
+        };
+        if picked.is_some() {
+            return Ok(picked);
+        }
+        let now = Instant::now();
+        match deadline {
+            Some(deadline) if now >= deadline => return Ok(None),
+            Some(deadline) => sleep(poll.min(deadline - now)),
+            None => sleep(poll),
+        }
+    }
+}
+
+/// Replaces this process with `command`, running on `gpus`; only returns if
+/// that fails.
+pub fn exec(command: &[String], gpus: &[FreeGpu], uuids: bool) -> io::Error {
+    let mut cmd = Command::new(&command[0]);
+    cmd.args(&command[1..])
+        .env("CUDA_VISIBLE_DEVICES", visible_devices(gpus, uuids));
+    if !uuids {
+        // Indices are NVML's, which CUDA only shares in PCI bus order.
+        cmd.env("CUDA_DEVICE_ORDER", "PCI_BUS_ID");
+    }
+    cmd.exec()
+}
@@ -0,0 +1,145 @@
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
+
+use chrono::Local;
+use gmon::collector::Collector;
+use gmon::snapshot::Snapshot;
+use gmon::vendors::error::BackendError;
+use gmon::vendors::traits::{GpuInfo, GpuProviderInfo};
+use terminal_size::{terminal_size, Height, Width};
+
+use crate::display;
+
+const TICK: Duration = Duration::from_millis(100);
+
+const HIDE_CURSOR: &str = "\x1b[?25l";
+const SHOW_CURSOR: &str = "\x1b[?25h";
+const CLEAR_SCREEN: &str = "\x1b[2J";
+const CURSOR_HOME: &str = "\x1b[H";
+const CLEAR_LINE_END: &str = "\x1b[K";
+const CLEAR_BELOW: &str = "\x1b[J";
+
+fn size() -> Option<(u16, u16)> {
+    terminal_size().map(|(Width(w), Height(h))| (w, h))
+}
+
+/// Hides the cursor while alive and restores it on drop, including when
+/// leaving through an error.
+struct Screen;
+
+impl Screen {
+    fn enter() -> Self {
+        print!("{}{}", HIDE_CURSOR, CLEAR_SCREEN);
+        Screen
+    }
+}
+
+impl Drop for Screen {
+    fn drop(&mut self) {
+        print!("{}", SHOW_CURSOR);
+        let _ = stdout().flush();
+    }
+}
+
+fn draw(lines: &str) {
+    let mut frame = String::from(CURSOR_HOME);
+    for line in lines.lines() {
+        frame.push_str(line);
+        frame.push_str(CLEAR_LINE_END);
+        frame.push('\n');
+    }
+    frame.push_str(CLEAR_BELOW);
+    let mut out = stdout();
+    let _ = out.write_all(frame.as_bytes());
+    let _ = out.flush();
+}
+
+fn interrupt_flag() -> Arc<AtomicBool> {
+    let running = Arc::new(AtomicBool::new(true));
+    {
+        let running = running.clone();
+        ctrlc::set_handler(move || running.store(false, Ordering::SeqCst))
+            .expect("failed to set Ctrl-C handler");
+    }
+    running
+}
+
+/// Re-samples every `interval` and writes `render`'s output for each sample,
+/// until Ctrl-C or until stdout is closed.
+pub fn stream<'a, P, G>(
+    collector: &mut Collector<'a, P>,
+    interval: Duration,
+    render: impl Fn(&Snapshot) -> String,
+) -> Result<(), BackendError>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    let running = interrupt_flag();
+    let mut next_sample = Instant::now();
+    let mut out = stdout();
+    while running.load(Ordering::SeqCst) {
+        if Instant::now() >= next_sample {
+            let snapshot = collector.sample()?;
+            let text = render(&snapshot);
+            if writeln!(out, "{}", text).and_then(|_| out.flush()).is_err() {
+                break;
+            }
+            next_sample += interval;
+        }
+        sleep(TICK.min(next_sample.saturating_duration_since(Instant::now())));
+    }
+    Ok(())
+}
+
+/// Re-samples every `interval` and redraws the header and `render`'s body in
+/// place until Ctrl-C.
+///
+/// The header clock ticks every second regardless of `interval`, and a
+/// terminal resize triggers a full redraw.
+pub fn run<'a, P, G>(
+    collector: &mut Collector<'a, P>,
+    interval: Duration,
+    render: impl Fn(&Snapshot) -> String,
+) -> Result<(), BackendError>
+where
+    P: GpuProviderInfo<'a, G>,
+    G: GpuInfo<'a> + Send,
+{
+    let running = interrupt_flag();
+    let _screen = Screen::enter();
+    let mut snapshot = collector.sample()?;
+    let mut body = render(&snapshot);
+    let mut next_sample = Instant::now() + interval;
+    let mut term_size = size();
+    let mut drawn_at = None;
+    while running.load(Ordering::SeqCst) {
+        let mut dirty = false;
+        if Instant::now() >= next_sample {
+            snapshot = collector.sample()?;
+            body = render(&snapshot);
+            next_sample += interval;
+            dirty = true;
+        }
+        let cur_size = size();
+        if cur_size != term_size {
+            term_size = cur_size;
+            print!("{}", CLEAR_SCREEN);
+            dirty = true;
+        }
+        let clock = Local::now();
+        if drawn_at != Some(clock.timestamp()) {
+            dirty = true;
+        }
+        if dirty {
+            let header = display::render_header(&snapshot, &clock, display::get_width());
+            draw(&format!("{}\n{}", header, body));
+            drawn_at = Some(clock.timestamp());
+        }
+        sleep(TICK.min(next_sample.saturating_duration_since(Instant::now())));
+    }
+    Ok(())
+}